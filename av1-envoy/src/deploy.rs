@@ -0,0 +1,195 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{ensure, Context, Result};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tracing::info;
+use utils::{
+    async_cmd,
+    macros::async_cmd::async_process::{Command, Stdio},
+};
+use volo_gen::av1::operator::{DeployReq, DeployResp, HealthCheckReq, HealthCheckResp, InstalledApp, ListInstalledResp};
+
+use crate::files;
+
+/// Every app version gets its own directory: `<APPS_DIR>/<app>/<version>`
+pub const APPS_DIR: &str = "/opt/av1-apps";
+/// `<APPS_DIR>/<app>/current` links to the last successfully installed version
+const CURRENT_LINK: &str = "current";
+
+pub fn version_dir(app: &str, version: &str) -> Result<PathBuf> {
+    for name in [app, version] {
+        ensure!(
+            !name.is_empty() && name != "." && name != ".." && !name.contains('/'),
+            "invalid app or version name: {:?}",
+            name
+        );
+    }
+    Ok(Path::new(APPS_DIR).join(app).join(version))
+}
+
+pub async fn deploy(req: DeployReq) -> Result<DeployResp> {
+    let DeployReq {
+        app,
+        version,
+        artifact,
+        artifact_sha256,
+        install_script,
+        artifact_path,
+    } = req;
+
+    let sha256 = if artifact_path.is_empty() {
+        format!("{:x}", Sha256::digest(&artifact))
+    } else {
+        files::file_sha256(Path::new(&*artifact_path)).await?
+    };
+    ensure!(
+        sha256 == *artifact_sha256,
+        "artifact sha256 mismatch. expect {}, got {}",
+        artifact_sha256,
+        sha256
+    );
+
+    // unpack into a clean dir, a redeploy of the same version must not see leftovers
+    let dir = version_dir(&app, &version)?;
+    if fs::try_exists(&dir).await? {
+        fs::remove_dir_all(&dir).await.context("clean version dir")?;
+    }
+    fs::create_dir_all(&dir).await.context("create version dir")?;
+
+    // an uploaded artifact is used where it is, and removed like a written one once unpacked
+    let tarball = if artifact_path.is_empty() {
+        let tarball = dir.with_extension("tar.gz");
+        fs::write(&tarball, &artifact).await.context("write artifact")?;
+        tarball
+    } else {
+        PathBuf::from(&*artifact_path)
+    };
+    let unpacked = async {
+        async_cmd!("tar", "-xzf", tarball, "-C", dir);
+        anyhow::Ok(())
+    }
+    .await;
+    fs::remove_file(&tarball).await?;
+    unpacked.context("unpack artifact")?;
+
+    let script_path = dir.join("install.sh");
+    fs::write(&script_path, install_script.as_bytes())
+        .await
+        .context("write install script")?;
+
+    info!(%app, %version, ?dir, "running install script");
+    let output = Command::new("bash")
+        .arg(&script_path)
+        .current_dir(&dir)
+        .env("APP_NAME", &*app)
+        .env("APP_VERSION", &*version)
+        .env("INSTALL_DIR", &dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("run install script")?;
+    if output.status.success() {
+        switch_current(&dir).await?;
+    }
+
+    Ok(DeployResp {
+        install_dir: dir.to_string_lossy().into_owned().into(),
+        exit_code: output.status.code().unwrap_or(-1),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned().into(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned().into(),
+    })
+}
+
+/// Point the `current` link of the app at `dir`, replacing the old link atomically
+async fn switch_current(dir: &Path) -> Result<()> {
+    let app_dir = dir.parent().context("version dir has no parent")?;
+    let tmp_link = app_dir.join(format!(".{}.tmp", CURRENT_LINK));
+    if fs::symlink_metadata(&tmp_link).await.is_ok() {
+        fs::remove_file(&tmp_link).await?;
+    }
+    fs::symlink(dir, &tmp_link).await.context("link current version")?;
+    fs::rename(&tmp_link, app_dir.join(CURRENT_LINK)).await?;
+    Ok(())
+}
+
+pub async fn list_installed() -> Result<ListInstalledResp> {
+    let mut apps = Vec::new();
+    if !fs::try_exists(APPS_DIR).await? {
+        return Ok(ListInstalledResp { apps });
+    }
+
+    let mut app_dirs = fs::read_dir(APPS_DIR).await?;
+    while let Some(app_dir) = app_dirs.next_entry().await? {
+        if !app_dir.file_type().await?.is_dir() {
+            continue;
+        }
+        let app = app_dir.file_name().to_string_lossy().into_owned();
+        let current = match fs::read_link(app_dir.path().join(CURRENT_LINK)).await {
+            Ok(target) => target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            Err(_) => String::new(),
+        };
+
+        let mut versions = Vec::new();
+        let mut version_dirs = fs::read_dir(app_dir.path()).await?;
+        while let Some(version_dir) = version_dirs.next_entry().await? {
+            // skips the current link and leftover tarballs
+            if version_dir.file_type().await?.is_dir() {
+                versions.push(version_dir.file_name().to_string_lossy().into_owned().into());
+            }
+        }
+
+        apps.push(InstalledApp {
+            app: app.into(),
+            current: current.into(),
+            versions,
+        });
+    }
+    Ok(ListInstalledResp { apps })
+}
+
+const HEALTH_SCRIPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub async fn check_health(req: HealthCheckReq) -> Result<HealthCheckResp> {
+    let HealthCheckReq { app, version, script } = req;
+    if script.trim().is_empty() {
+        return Ok(HealthCheckResp {
+            healthy: true,
+            output: "no health script".into(),
+        });
+    }
+
+    let dir = version_dir(&app, &version)?;
+    ensure!(fs::try_exists(&dir).await?, "version {} of {} is not installed", version, app);
+
+    let child = Command::new("bash")
+        .arg("-c")
+        .arg(&*script)
+        .current_dir(&dir)
+        .env("APP_NAME", &*app)
+        .env("APP_VERSION", &*version)
+        .env("INSTALL_DIR", &dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+    let Ok(output) = tokio::time::timeout(HEALTH_SCRIPT_TIMEOUT, child).await else {
+        return Ok(HealthCheckResp {
+            healthy: false,
+            output: "health script timed out".into(),
+        });
+    };
+    let output = output.context("run health script")?;
+
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(HealthCheckResp {
+        healthy: output.status.success(),
+        output: text.into(),
+    })
+}
@@ -1,8 +1,16 @@
-use tracing::debug;
-use volo_gen::av1::operator::{self, Ping, Pong};
-use volo_grpc::{Request, Response};
+use futures::{StreamExt, TryStreamExt};
+use tracing::{debug, error, warn};
+use volo_gen::{
+    av1::operator::{
+        self, DeployReq, DeployResp, ExecOutput, ExecReq, GetFactsReq, GetFactsResp, GetFileChunk, GetFileReq, HandshakeReq, HandshakeResp,
+        HealthCheckReq, HealthCheckResp, JournalLine, JournalReq, ListInstalledReq, ListInstalledResp, MetricsReq, MetricsSample, Ping,
+        Pong, PutFileReq, PutFileResp, ServiceControlReq, ServiceStatus, UploadOffsetReq, UploadOffsetResp,
+    },
+    PROTOCOL_VERSION,
+};
+use volo_grpc::{BoxStream, RecvStream, Request, Response, Status};
 
-use crate::RpcResult;
+use crate::{deploy, exec, facts, files, journal, metrics, service, uptime_secs, RpcResult};
 
 pub struct Host;
 
@@ -13,4 +21,90 @@ impl operator::NodeService for Host {
             message: req.into_inner().message,
         }))
     }
+
+    async fn handshake(&self, req: Request<HandshakeReq>) -> RpcResult<HandshakeResp> {
+        let req = req.into_inner();
+        if req.protocol_version != PROTOCOL_VERSION {
+            warn!(
+                operator = req.protocol_version,
+                envoy = PROTOCOL_VERSION,
+                "protocol version mismatch"
+            );
+        }
+        let hostname = tokio::fs::read_to_string("/proc/sys/kernel/hostname")
+            .await
+            .map(|h| h.trim().to_string())
+            .unwrap_or_default();
+        Ok(Response::new(HandshakeResp {
+            crate_version: env!("CARGO_PKG_VERSION").into(),
+            git_commit: env!("GIT_COMMIT").into(),
+            protocol_version: PROTOCOL_VERSION,
+            hostname: hostname.into(),
+            uptime_secs: uptime_secs(),
+        }))
+    }
+
+    async fn deploy(&self, req: Request<DeployReq>) -> RpcResult<DeployResp> {
+        let req = req.into_inner();
+        debug!(app = %req.app, version = %req.version, "deploy");
+        let resp = deploy::deploy(req).await.map_err(internal)?;
+        Ok(Response::new(resp))
+    }
+
+    async fn check_health(&self, req: Request<HealthCheckReq>) -> RpcResult<HealthCheckResp> {
+        let resp = deploy::check_health(req.into_inner()).await.map_err(internal)?;
+        Ok(Response::new(resp))
+    }
+
+    async fn list_installed(&self, _req: Request<ListInstalledReq>) -> RpcResult<ListInstalledResp> {
+        let resp = deploy::list_installed().await.map_err(internal)?;
+        Ok(Response::new(resp))
+    }
+
+    async fn get_facts(&self, _req: Request<GetFactsReq>) -> RpcResult<GetFactsResp> {
+        let resp = facts::get_facts().await.map_err(internal)?;
+        Ok(Response::new(resp))
+    }
+
+    async fn metrics(&self, req: Request<MetricsReq>) -> RpcResult<BoxStream<'static, Result<MetricsSample, Status>>> {
+        let MetricsReq { interval_ms } = req.into_inner();
+        debug!(interval_ms, "stream metrics");
+        let samples = metrics::metrics(interval_ms).map_err(internal);
+        Ok(Response::new(Box::pin(samples)))
+    }
+
+    async fn exec(&self, req: Request<ExecReq>) -> RpcResult<BoxStream<'static, Result<ExecOutput, Status>>> {
+        let output = exec::exec(req.into_inner()).map_err(internal)?;
+        Ok(Response::new(Box::pin(output.map(Ok))))
+    }
+
+    async fn put_file(&self, req: Request<RecvStream<PutFileReq>>) -> RpcResult<PutFileResp> {
+        let resp = files::put_file(req.into_inner()).await.map_err(internal)?;
+        Ok(Response::new(resp))
+    }
+
+    async fn upload_offset(&self, req: Request<UploadOffsetReq>) -> RpcResult<UploadOffsetResp> {
+        let resp = files::upload_offset(req.into_inner()).await.map_err(internal)?;
+        Ok(Response::new(resp))
+    }
+
+    async fn get_file(&self, req: Request<GetFileReq>) -> RpcResult<BoxStream<'static, Result<GetFileChunk, Status>>> {
+        let chunks = files::get_file(&req.into_inner().path).await.map_err(internal)?;
+        Ok(Response::new(Box::pin(chunks.map_err(internal))))
+    }
+
+    async fn service_control(&self, req: Request<ServiceControlReq>) -> RpcResult<ServiceStatus> {
+        let resp = service::service_control(req.into_inner()).await.map_err(internal)?;
+        Ok(Response::new(resp))
+    }
+
+    async fn journal(&self, req: Request<JournalReq>) -> RpcResult<BoxStream<'static, Result<JournalLine, Status>>> {
+        let lines = journal::journal(req.into_inner()).map_err(internal)?;
+        Ok(Response::new(Box::pin(lines.map_err(internal))))
+    }
+}
+
+fn internal(err: anyhow::Error) -> Status {
+    error!(?err, "rpc failed");
+    Status::internal(format!("{:#}", err))
 }
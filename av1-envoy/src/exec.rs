@@ -0,0 +1,138 @@
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{ensure, Context, Result};
+use futures::{stream, Stream};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::Command,
+    sync::mpsc,
+};
+use tracing::{info, warn};
+use volo_gen::av1::operator::{exec_output::Output, ExecExit, ExecOutput, ExecReq};
+
+/// Output chunks waiting for a slow reader before the command blocks on its pipes
+const BUFFERED_CHUNKS: usize = 64;
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Start the command and stream its output, ending with its exit.
+/// The command and everything it started are killed when the stream is dropped or the timeout runs out.
+pub fn exec(req: ExecReq) -> Result<impl Stream<Item = ExecOutput>> {
+    let ExecReq {
+        command,
+        args,
+        env,
+        cwd,
+        timeout_secs,
+        script,
+    } = req;
+    ensure!(
+        command.is_empty() != script.is_empty(),
+        "exactly one of command and script is required"
+    );
+
+    let mut cmd = if script.is_empty() {
+        Command::new(&*command)
+    } else {
+        let mut cmd = Command::new("bash");
+        // bash takes the argument after the script as $0
+        cmd.arg("-c").arg(&*script).arg("bash");
+        cmd
+    };
+    cmd.args(args.iter().map(|a| &**a))
+        .envs(env.iter().map(|(k, v)| (&**k, &**v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // its own process group, so whatever it starts can be killed along with it
+        .process_group(0)
+        .kill_on_drop(true);
+    if !cwd.is_empty() {
+        cmd.current_dir(&*cwd);
+    }
+    let what = if script.is_empty() { &*command } else { "script" };
+    let child = cmd.spawn().with_context(|| format!("spawn {}", what))?;
+    info!(cmd = what, pid = child.id(), "exec");
+
+    let (tx, rx) = mpsc::channel(BUFFERED_CHUNKS);
+    let timeout = (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs.into()));
+    tokio::spawn(run(child, timeout, tx));
+
+    Ok(stream::unfold(rx, |mut rx| async move {
+        let output = rx.recv().await?;
+        Some((ExecOutput { output: Some(output) }, rx))
+    }))
+}
+
+async fn run(mut child: tokio::process::Child, timeout: Option<Duration>, tx: mpsc::Sender<Output>) {
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let pid = child.id();
+
+    let finished = async {
+        tokio::join!(pump(stdout, Output::Stdout, &tx), pump(stderr, Output::Stderr, &tx));
+        child.wait().await
+    };
+    let deadline = async {
+        match timeout {
+            Some(timeout) => tokio::time::sleep(timeout).await,
+            None => std::future::pending().await,
+        }
+    };
+    let exit = tokio::select! {
+        status = finished => match status {
+            Ok(status) => ExecExit {
+                code: status.code().unwrap_or(-1),
+                timed_out: false,
+            },
+            Err(err) => {
+                warn!(?err, "wait for command");
+                ExecExit { code: -1, timed_out: false }
+            }
+        },
+        _ = deadline => {
+            info!(pid, "command timed out");
+            ExecExit { code: -1, timed_out: true }
+        }
+        // nobody is listening any more
+        _ = tx.closed() => {
+            info!(pid, "exec cancelled");
+            kill_group(pid);
+            return;
+        }
+    };
+    if exit.timed_out {
+        kill_group(pid);
+        if let Err(err) = child.kill().await {
+            warn!(?err, "kill timed out command");
+        }
+    }
+    let _ = tx.send(Output::Exit(exit)).await;
+}
+
+/// Kill the command and everything it started, like the jobs of a script
+fn kill_group(pid: Option<u32>) {
+    let Some(pid) = pid else { return };
+    // the command leads its group, the group id is its pid
+    if unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) } != 0 {
+        warn!(err = ?std::io::Error::last_os_error(), pid, "kill process group");
+    }
+}
+
+/// Forward everything `reader` yields until it closes or the receiver is gone
+async fn pump(mut reader: impl AsyncRead + Unpin, wrap: fn(pilota::Bytes) -> Output, tx: &mpsc::Sender<Output>) {
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        match reader.read(&mut buf).await {
+            Ok(0) => return,
+            Ok(n) => {
+                if tx.send(wrap(pilota::Bytes::copy_from_slice(&buf[..n]))).await.is_err() {
+                    return;
+                }
+            }
+            Err(err) => {
+                warn!(?err, "read command output");
+                return;
+            }
+        }
+    }
+}
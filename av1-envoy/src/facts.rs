@@ -0,0 +1,128 @@
+use anyhow::{ensure, Context, Result};
+use tokio::fs;
+use utils::macros::async_cmd::async_process::Command;
+use volo_gen::av1::operator::{Disk, GetFactsResp};
+
+/// Filesystems that hold no data worth placing work on
+const PSEUDO_FS: &[&str] = &["tmpfs", "devtmpfs", "squashfs", "overlay", "efivarfs"];
+
+pub async fn get_facts() -> Result<GetFactsResp> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").await.context("read cpuinfo")?;
+    let meminfo = fs::read_to_string("/proc/meminfo").await.context("read meminfo")?;
+    let os_release = fs::read_to_string("/etc/os-release").await.unwrap_or_default();
+    let kernel = fs::read_to_string("/proc/sys/kernel/osrelease")
+        .await
+        .context("read kernel release")?;
+    let cpu = parse_cpuinfo(&cpuinfo);
+
+    Ok(GetFactsResp {
+        cpu_model: cpu.model.into(),
+        cpu_cores: cpu.cores,
+        cpu_flags: cpu.flags.into_iter().map(Into::into).collect(),
+        mem_total_bytes: meminfo_bytes(&meminfo, "MemTotal").context("no MemTotal in meminfo")?,
+        mem_available_bytes: meminfo_bytes(&meminfo, "MemAvailable").unwrap_or_default(),
+        disks: disks().await?,
+        os_release: os_release_name(&os_release).into(),
+        kernel: kernel.trim().to_string().into(),
+        arch: std::env::consts::ARCH.into(),
+    })
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct CpuInfo {
+    model: String,
+    cores: u32,
+    flags: Vec<String>,
+}
+
+/// Model and flags are taken from the first processor, they are the same on every core
+fn parse_cpuinfo(cpuinfo: &str) -> CpuInfo {
+    let mut cpu = CpuInfo::default();
+    for line in cpuinfo.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "processor" => cpu.cores += 1,
+            // arm reports no model name
+            "model name" | "Model" if cpu.model.is_empty() => cpu.model = value.to_string(),
+            "flags" | "Features" if cpu.flags.is_empty() => cpu.flags = value.split_whitespace().map(str::to_string).collect(),
+            _ => {}
+        }
+    }
+    cpu
+}
+
+/// A `kB` field of /proc/meminfo in bytes
+pub(crate) fn meminfo_bytes(meminfo: &str, field: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let value = line.strip_prefix(field)?.strip_prefix(':')?;
+        let kb: u64 = value.trim().trim_end_matches("kB").trim().parse().ok()?;
+        Some(kb * 1024)
+    })
+}
+
+fn os_release_name(os_release: &str) -> String {
+    os_release
+        .lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|name| name.trim_matches('"').to_string())
+        .unwrap_or_default()
+}
+
+async fn disks() -> Result<Vec<Disk>> {
+    let mut cmd = Command::new("df");
+    cmd.args(["--local", "--block-size=1", "--output=fstype,size,avail,target"]);
+    for fs in PSEUDO_FS {
+        cmd.arg(format!("--exclude-type={}", fs));
+    }
+    let output = cmd.output().await.context("run df")?;
+    ensure!(output.status.success(), "df failed: {}", String::from_utf8_lossy(&output.stderr));
+    Ok(parse_df(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn parse_df(df: &str) -> Vec<Disk> {
+    df.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let filesystem = fields.next()?;
+            let total_bytes = fields.next()?.parse().ok()?;
+            let available_bytes = fields.next()?.parse().ok()?;
+            // the mount point may contain spaces
+            let mount_point = fields.collect::<Vec<_>>().join(" ");
+            Some(Disk {
+                mount_point: mount_point.into(),
+                filesystem: filesystem.to_string().into(),
+                total_bytes,
+                available_bytes,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_parse() {
+        let cpuinfo = "processor\t: 0\nmodel name\t: AMD EPYC 7R13\nflags\t\t: fpu avx2 avx512f\n\n\
+                       processor\t: 1\nmodel name\t: AMD EPYC 7R13\nflags\t\t: fpu avx2 avx512f\n";
+        let cpu = parse_cpuinfo(cpuinfo);
+        assert_eq!(cpu.model, "AMD EPYC 7R13");
+        assert_eq!(cpu.cores, 2);
+        assert_eq!(cpu.flags, ["fpu", "avx2", "avx512f"]);
+
+        let meminfo = "MemTotal:       16318412 kB\nMemFree:         1000 kB\nMemAvailable:    2048 kB\n";
+        assert_eq!(meminfo_bytes(meminfo, "MemTotal"), Some(16318412 * 1024));
+        assert_eq!(meminfo_bytes(meminfo, "MemAvailable"), Some(2048 * 1024));
+
+        let df = "Type     1B-blocks     Avail Mounted on\next4   1000000 400000 /\nxfs 20 10 /mnt/my disk\n";
+        let disks = parse_df(df);
+        assert_eq!(disks.len(), 2);
+        assert_eq!(&*disks[1].mount_point, "/mnt/my disk");
+        assert_eq!(disks[0].available_bytes, 400000);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use futures::{stream, Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tracing::info;
+use utils::macros::async_cmd::async_process::Command;
+use volo_gen::av1::operator::{GetFileChunk, PutFileHeader, PutFileReq, PutFileResp, UploadOffsetReq, UploadOffsetResp};
+use volo_grpc::RecvStream;
+
+const DEFAULT_MODE: u32 = 0o644;
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// Received data waits here until the upload is complete and verified.
+/// The sha256 is part of the name so a resumed upload never continues another file.
+fn part_path(path: &Path, sha256: &str) -> Result<PathBuf> {
+    let name = path.file_name().context("path has no file name")?.to_string_lossy();
+    Ok(path.with_file_name(format!(".{}.{}.part", name, sha256)))
+}
+
+fn target_path(path: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(path);
+    ensure!(path.is_absolute(), "path must be absolute: {:?}", path);
+    Ok(path)
+}
+
+fn check_sha256(sha256: &str) -> Result<()> {
+    ensure!(
+        sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit()),
+        "invalid sha256: {:?}",
+        sha256
+    );
+    Ok(())
+}
+
+/// How much of an upload of `sha256` to `path` has been received already
+pub async fn upload_offset(req: UploadOffsetReq) -> Result<UploadOffsetResp> {
+    check_sha256(&req.sha256)?;
+    let part = part_path(&target_path(&req.path)?, &req.sha256)?;
+    let offset = match fs::metadata(&part).await {
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    };
+    Ok(UploadOffsetResp { offset })
+}
+
+/// Append the received chunks to the part file, then verify and move it into place.
+/// An interrupted upload leaves the part file behind to be resumed.
+pub async fn put_file(mut chunks: RecvStream<PutFileReq>) -> Result<PutFileResp> {
+    let first = chunks.next().await.context("empty upload")??;
+    let header = first.header.context("the first message has no header")?;
+    let PutFileHeader {
+        path,
+        mode,
+        owner,
+        size,
+        sha256,
+        offset,
+    } = header;
+    check_sha256(&sha256)?;
+    let path = target_path(&path)?;
+    let part = part_path(&path, &sha256)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await.context("create parent dir")?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&part)
+        .await
+        .context("open part file")?;
+    let received = file.metadata().await?.len();
+    ensure!(
+        offset <= received,
+        "upload starts at {} but only {} bytes were received",
+        offset,
+        received
+    );
+    // anything after the offset was not confirmed to the sender
+    file.set_len(offset).await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    info!(?path, size, offset, "receiving file");
+
+    file.write_all(&first.data).await.context("write part file")?;
+    while let Some(chunk) = chunks.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                // keep what arrived for the resume
+                file.sync_data().await?;
+                return Err(anyhow::Error::from(err).context("upload interrupted"));
+            }
+        };
+        file.write_all(&chunk.data).await.context("write part file")?;
+    }
+    file.sync_all().await.context("sync part file")?;
+    drop(file);
+
+    let received = fs::metadata(&part).await?.len();
+    if received != size {
+        bail!("upload ended at {} of {} bytes", received, size);
+    }
+    let actual = file_sha256(&part).await?;
+    if actual != *sha256 {
+        fs::remove_file(&part).await?;
+        bail!("sha256 mismatch. expect {}, got {}", sha256, actual);
+    }
+
+    let mode = if mode == 0 { DEFAULT_MODE } else { mode };
+    fs::set_permissions(&part, std::fs::Permissions::from_mode(mode))
+        .await
+        .context("set mode")?;
+    if !owner.is_empty() {
+        let output = Command::new("chown").arg(&*owner).arg(&part).output().await.context("run chown")?;
+        ensure!(
+            output.status.success(),
+            "chown {} failed: {}",
+            owner,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    fs::rename(&part, &path).await.context("move file into place")?;
+    info!(?path, size, "file received");
+    Ok(PutFileResp { size })
+}
+
+pub async fn file_sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await.with_context(|| format!("open {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+struct Download {
+    file: File,
+    hasher: Sha256,
+    /// size and mode still to be sent
+    header: Option<(u64, u32)>,
+    buf: Vec<u8>,
+}
+
+/// Stream the file in chunks, the last one carries the sha256 of everything sent
+pub async fn get_file(path: &str) -> Result<impl Stream<Item = Result<GetFileChunk>>> {
+    let path = target_path(path)?;
+    let file = File::open(&path).await.with_context(|| format!("open {:?}", path))?;
+    let meta = file.metadata().await?;
+    ensure!(meta.is_file(), "{:?} is not a file", path);
+    info!(?path, size = meta.len(), "sending file");
+
+    let download = Download {
+        file,
+        hasher: Sha256::new(),
+        header: Some((meta.len(), meta.permissions().mode() & 0o7777)),
+        buf: vec![0; CHUNK_SIZE],
+    };
+    Ok(stream::try_unfold(Some(download), |download| async move {
+        let Some(mut download) = download else { return Ok(None) };
+        let n = download.file.read(&mut download.buf).await?;
+        download.hasher.update(&download.buf[..n]);
+
+        let (size, mode) = download.header.take().unwrap_or_default();
+        let mut chunk = GetFileChunk {
+            data: pilota::Bytes::copy_from_slice(&download.buf[..n]),
+            size,
+            mode,
+            sha256: Default::default(),
+        };
+        if n == 0 {
+            chunk.sha256 = format!("{:x}", download.hasher.finalize()).into();
+            return Ok(Some((chunk, None)));
+        }
+        Ok(Some((chunk, Some(download))))
+    }))
+}
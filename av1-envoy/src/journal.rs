@@ -0,0 +1,63 @@
+use std::process::Stdio;
+
+use anyhow::{bail, Context, Result};
+use futures::{stream, Stream};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    process::{Child, Command},
+};
+use tracing::info;
+use volo_gen::av1::operator::{JournalLine, JournalReq};
+
+use crate::service::check_unit;
+
+/// Stream the journal of the unit line by line. journalctl is killed when the stream is dropped.
+pub fn journal(req: JournalReq) -> Result<impl Stream<Item = Result<JournalLine>>> {
+    let JournalReq {
+        unit,
+        since,
+        lines,
+        follow,
+    } = req;
+    check_unit(&unit)?;
+
+    let mut cmd = Command::new("journalctl");
+    cmd.args(["--no-pager", "--output=short-iso", "--unit"]).arg(&*unit);
+    if !since.is_empty() {
+        cmd.arg(format!("--since={}", since));
+    }
+    if lines > 0 {
+        cmd.arg(format!("--lines={}", lines));
+    }
+    if follow {
+        cmd.arg("--follow");
+    }
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = cmd.spawn().context("spawn journalctl")?;
+    info!(%unit, %since, lines, follow, pid = child.id(), "journal");
+
+    let stdout = BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+    Ok(stream::try_unfold((child, stdout), |(mut child, mut stdout)| async move {
+        if let Some(line) = stdout.next_line().await.context("read journalctl output")? {
+            return Ok(Some((JournalLine { line: line.into() }, (child, stdout))));
+        }
+        check_exit(&mut child).await?;
+        Ok(None)
+    }))
+}
+
+/// journalctl reports a bad `since` and the like only on stderr and through its exit code
+async fn check_exit(child: &mut Child) -> Result<()> {
+    let status = child.wait().await.context("wait for journalctl")?;
+    if !status.success() {
+        let mut stderr = String::new();
+        if let Some(mut pipe) = child.stderr.take() {
+            pipe.read_to_string(&mut stderr).await?;
+        }
+        bail!("journalctl failed: {}", stderr.trim());
+    }
+    Ok(())
+}
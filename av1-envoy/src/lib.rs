@@ -1,12 +1,29 @@
+use std::{sync::OnceLock, time::Instant};
+
 use anyhow::Result;
 use utils::logger::{self, Config};
 use volo_grpc::Status;
 
+pub mod deploy;
 pub mod endpoint;
+pub mod exec;
+pub mod facts;
+pub mod files;
+pub mod journal;
+pub mod metrics;
+pub mod service;
 
 type RpcResult<T> = Result<volo_grpc::Response<T>, Status>;
 
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+
+/// Seconds since `init_global`
+pub fn uptime_secs() -> u64 {
+    STARTED_AT.get().map_or(0, |t| t.elapsed().as_secs())
+}
+
 pub async fn init_global() -> Result<()> {
+    STARTED_AT.get_or_init(Instant::now);
     logger::init(&Config {
         level: "debug".to_string(),
     })?;
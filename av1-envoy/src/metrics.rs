@@ -0,0 +1,148 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use futures::{stream, Stream};
+use tokio::{fs, time::MissedTickBehavior};
+use volo_gen::av1::operator::MetricsSample;
+
+use crate::facts::meminfo_bytes;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+/// Sampling /proc faster than this only burns the cpu being measured
+const MIN_INTERVAL: Duration = Duration::from_millis(200);
+/// /proc/diskstats counts 512 byte sectors whatever the disk uses
+const SECTOR_SIZE: u64 = 512;
+
+/// Sample the host every `interval_ms` until the stream is dropped
+pub fn metrics(interval_ms: u32) -> impl Stream<Item = Result<MetricsSample>> {
+    let interval = match interval_ms {
+        0 => DEFAULT_INTERVAL,
+        ms => Duration::from_millis(ms.into()).max(MIN_INTERVAL),
+    };
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    stream::unfold((ticker, CpuTimes::default()), |(mut ticker, prev)| async move {
+        ticker.tick().await;
+        let sample = sample(&prev).await;
+        let cpu = sample.as_ref().map_or(prev, |(_, cpu)| *cpu);
+        Some((sample.map(|(sample, _)| sample), (ticker, cpu)))
+    })
+}
+
+async fn sample(prev: &CpuTimes) -> Result<(MetricsSample, CpuTimes)> {
+    let stat = fs::read_to_string("/proc/stat").await.context("read stat")?;
+    let meminfo = fs::read_to_string("/proc/meminfo").await.context("read meminfo")?;
+    let loadavg = fs::read_to_string("/proc/loadavg").await.context("read loadavg")?;
+    let diskstats = fs::read_to_string("/proc/diskstats").await.context("read diskstats")?;
+    let net_dev = fs::read_to_string("/proc/net/dev").await.context("read net dev")?;
+
+    let cpu = CpuTimes::parse(&stat).context("no cpu line in stat")?;
+    let mut load = loadavg.split_whitespace().map(|l| l.parse().unwrap_or_default());
+    let (disk_read_bytes, disk_written_bytes) = disk_bytes(&diskstats);
+    let (net_rx_bytes, net_tx_bytes) = net_bytes(&net_dev);
+
+    let sample = MetricsSample {
+        timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64,
+        cpu_percent: cpu.busy_percent_since(prev),
+        mem_total_bytes: meminfo_bytes(&meminfo, "MemTotal").unwrap_or_default(),
+        mem_available_bytes: meminfo_bytes(&meminfo, "MemAvailable").unwrap_or_default(),
+        load1: load.next().unwrap_or_default(),
+        load5: load.next().unwrap_or_default(),
+        load15: load.next().unwrap_or_default(),
+        disk_read_bytes,
+        disk_written_bytes,
+        net_rx_bytes,
+        net_tx_bytes,
+    };
+    Ok((sample, cpu))
+}
+
+/// Jiffies of all cores from the `cpu` line of /proc/stat
+#[derive(Debug, Default, Clone, Copy)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+impl CpuTimes {
+    fn parse(stat: &str) -> Option<Self> {
+        let line = stat.lines().find(|l| l.starts_with("cpu "))?;
+        let fields: Vec<u64> = line.split_whitespace().skip(1).map(|f| f.parse().unwrap_or_default()).collect();
+        // user nice system idle iowait irq softirq steal, guest time is already in user
+        let total: u64 = fields.iter().take(8).sum();
+        let idle = fields.get(3).copied().unwrap_or_default() + fields.get(4).copied().unwrap_or_default();
+        Some(Self { busy: total - idle, total })
+    }
+
+    fn busy_percent_since(&self, prev: &CpuTimes) -> f64 {
+        let total = self.total.saturating_sub(prev.total);
+        if total == 0 {
+            return 0.0;
+        }
+        self.busy.saturating_sub(prev.busy) as f64 * 100.0 / total as f64
+    }
+}
+
+/// Bytes read and written by whole disks, partitions would count twice
+fn disk_bytes(diskstats: &str) -> (u64, u64) {
+    let mut read = 0;
+    let mut written = 0;
+    for line in diskstats.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let Some(name) = fields.get(2) else { continue };
+        if !is_disk(name) {
+            continue;
+        }
+        let field = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok()).unwrap_or_default();
+        read += field(5) * SECTOR_SIZE;
+        written += field(9) * SECTOR_SIZE;
+    }
+    (read, written)
+}
+
+fn is_disk(name: &str) -> bool {
+    if ["loop", "ram", "zram", "dm-", "sr"].iter().any(|p| name.starts_with(p)) {
+        return false;
+    }
+    // only whole disks have a /sys/block entry
+    std::path::Path::new("/sys/block").join(name).exists()
+}
+
+/// Bytes received and sent by every interface but loopback
+fn net_bytes(net_dev: &str) -> (u64, u64) {
+    let mut rx = 0;
+    let mut tx = 0;
+    // two header lines
+    for line in net_dev.lines().skip(2) {
+        let Some((iface, counters)) = line.split_once(':') else {
+            continue;
+        };
+        if iface.trim() == "lo" {
+            continue;
+        }
+        let counters: Vec<u64> = counters.split_whitespace().map(|c| c.parse().unwrap_or_default()).collect();
+        rx += counters.first().copied().unwrap_or_default();
+        tx += counters.get(8).copied().unwrap_or_default();
+    }
+    (rx, tx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_parse() {
+        let prev = CpuTimes::parse("cpu  100 0 100 700 100 0 0 0 0 0\ncpu0 1 2 3 4\n").unwrap();
+        let cpu = CpuTimes::parse("cpu  150 0 150 800 100 0 0 0 0 0\n").unwrap();
+        assert_eq!(cpu.busy_percent_since(&prev), 50.0);
+        assert_eq!(cpu.busy_percent_since(&cpu), 0.0);
+
+        let net_dev = "Inter-|   Receive                        |  Transmit\n \
+                       face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets\n    \
+                       lo: 500 5 0 0 0 0 0 0 500 5 0 0 0 0 0 0\n  \
+                       eth0: 1000 10 0 0 0 0 0 0 2000 20 0 0 0 0 0 0\n";
+        assert_eq!(net_bytes(net_dev), (1000, 2000));
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::{bail, ensure, Context, Result};
+use tracing::info;
+use utils::macros::async_cmd::async_process::Command;
+use volo_gen::av1::operator::{ServiceControlReq, ServiceStatus};
+
+const ACTIONS: &[&str] = &["start", "stop", "restart", "enable", "disable"];
+const PROPERTIES: &str = "Id,LoadState,ActiveState,SubState,UnitFileState,MainPID,NRestarts";
+
+/// Run the action on the unit if there is one, then read the status of the unit
+pub async fn service_control(req: ServiceControlReq) -> Result<ServiceStatus> {
+    let ServiceControlReq { unit, action } = req;
+    check_unit(&unit)?;
+    if !action.is_empty() {
+        ensure!(ACTIONS.contains(&&*action), "unknown action {:?}", action);
+        info!(%unit, %action, "systemctl");
+        systemctl(&[&action, "--", &unit]).await?;
+    }
+    let show = systemctl(&["show", &format!("--property={}", PROPERTIES), "--", &unit]).await?;
+    Ok(parse_show(&show))
+}
+
+/// Unit names never hold whitespace or start with a dash, so none can be taken for an option
+pub(crate) fn check_unit(unit: &str) -> Result<()> {
+    ensure!(
+        !unit.is_empty() && !unit.starts_with('-') && unit.chars().all(|c| c.is_ascii_alphanumeric() || ":-_.\\@".contains(c)),
+        "invalid unit name {:?}",
+        unit
+    );
+    Ok(())
+}
+
+async fn systemctl(args: &[&str]) -> Result<String> {
+    let output = Command::new("systemctl")
+        .arg("--no-pager")
+        .args(args)
+        .output()
+        .await
+        .context("run systemctl")?;
+    if !output.status.success() {
+        bail!(
+            "systemctl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `systemctl show` prints one `Key=value` per line
+fn parse_show(show: &str) -> ServiceStatus {
+    let mut status = ServiceStatus::default();
+    for (key, value) in show.lines().filter_map(|line| line.split_once('=')) {
+        let text = || value.to_string().into();
+        match key {
+            "Id" => status.unit = text(),
+            "LoadState" => status.load_state = text(),
+            "ActiveState" => status.active_state = text(),
+            "SubState" => status.sub_state = text(),
+            "UnitFileState" => status.unit_file_state = text(),
+            "MainPID" => status.main_pid = value.parse().unwrap_or_default(),
+            // `[not set]` for units that are not services
+            "NRestarts" => status.restarts = value.parse().unwrap_or_default(),
+            _ => {}
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_parse() {
+        let show = "Id=nginx.service\nLoadState=loaded\nActiveState=active\nSubState=running\n\
+                    UnitFileState=enabled\nMainPID=812\nNRestarts=3\n";
+        let status = parse_show(show);
+        assert_eq!(&*status.unit, "nginx.service");
+        assert_eq!(&*status.active_state, "active");
+        assert_eq!(&*status.sub_state, "running");
+        assert_eq!((status.main_pid, status.restarts), (812, 3));
+
+        assert!(check_unit("encoder@1.service").is_ok());
+        assert!(check_unit("--now").is_err());
+        assert!(check_unit("a b").is_err());
+    }
+}
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE builds (
+    id BIGINT PRIMARY KEY NOT NULL,
+    app_id BIGINT NOT NULL,
+    hash TEXT NOT NULL,
+    commit_hash TEXT,
+    state SMALLINT NOT NULL,
+    exit_code INTEGER,
+    started_at DATETIME,
+    ended_at DATETIME,
+
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX builds_app_id ON builds (app_id);
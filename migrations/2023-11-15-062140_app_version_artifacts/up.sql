@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE app_versions ADD COLUMN artifact TEXT;
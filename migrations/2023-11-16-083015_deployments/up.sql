@@ -0,0 +1,32 @@
+-- Your SQL goes here
+CREATE TABLE deployments (
+    id BIGINT PRIMARY KEY NOT NULL,
+    app_id BIGINT NOT NULL,
+    hash TEXT NOT NULL,
+    triggered_by TEXT NOT NULL,
+    -- the deployment this one rolls back, if it is a rollback
+    rollback_of BIGINT,
+    state SMALLINT NOT NULL,
+    started_at DATETIME NOT NULL,
+    ended_at DATETIME,
+
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX deployments_app_id ON deployments (app_id);
+
+CREATE TABLE deployment_hosts (
+    deployment_id BIGINT NOT NULL,
+    host_id BIGINT NOT NULL,
+    state SMALLINT NOT NULL,
+    install_dir TEXT,
+    exit_code INTEGER,
+    stdout TEXT NOT NULL,
+    stderr TEXT NOT NULL,
+    error TEXT,
+
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (deployment_id, host_id)
+);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE deployments ADD COLUMN strategy TEXT NOT NULL DEFAULT '{"kind":"allAtOnce"}';
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE app_instances (
+    app_id BIGINT NOT NULL,
+    host_id BIGINT NOT NULL,
+    -- the version last deployed by the operator
+    hash TEXT NOT NULL,
+    install_dir TEXT,
+    state SMALLINT NOT NULL,
+    -- the current version as last reported by the envoy
+    reported_hash TEXT,
+    reported_at DATETIME,
+
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (app_id, host_id)
+);
+
+CREATE INDEX app_instances_host_id ON app_instances (host_id);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE desired_states;
+DROP TABLE host_group_members;
+DROP TABLE host_groups;
@@ -0,0 +1,26 @@
+-- Your SQL goes here
+CREATE TABLE host_groups (
+    id BIGINT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE host_group_members (
+    group_id BIGINT NOT NULL,
+    host_id BIGINT NOT NULL,
+
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (group_id, host_id)
+);
+
+-- what the reconciler converges to, one version and one host group per app
+CREATE TABLE desired_states (
+    app_id BIGINT PRIMARY KEY NOT NULL,
+    hash TEXT NOT NULL,
+    group_id BIGINT NOT NULL,
+
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
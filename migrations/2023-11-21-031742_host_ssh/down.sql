@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE hosts DROP COLUMN ssh_key;
+ALTER TABLE hosts DROP COLUMN ssh_user;
+ALTER TABLE hosts DROP COLUMN ssh_port;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE hosts ADD COLUMN ssh_port INTEGER NOT NULL DEFAULT 22;
+ALTER TABLE hosts ADD COLUMN ssh_user TEXT NOT NULL DEFAULT 'root';
+ALTER TABLE hosts ADD COLUMN ssh_key TEXT;
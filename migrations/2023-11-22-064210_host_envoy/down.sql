@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE hosts DROP COLUMN envoy_protocol;
+ALTER TABLE hosts DROP COLUMN envoy_commit;
+ALTER TABLE hosts DROP COLUMN envoy_version;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE hosts ADD COLUMN envoy_version TEXT;
+ALTER TABLE hosts ADD COLUMN envoy_commit TEXT;
+ALTER TABLE hosts ADD COLUMN envoy_protocol INTEGER;
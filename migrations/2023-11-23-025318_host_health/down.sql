@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE hosts DROP COLUMN failures;
+ALTER TABLE hosts DROP COLUMN last_seen_at;
+ALTER TABLE hosts DROP COLUMN state;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE hosts ADD COLUMN state SMALLINT NOT NULL DEFAULT 2;
+ALTER TABLE hosts ADD COLUMN last_seen_at DATETIME;
+ALTER TABLE hosts ADD COLUMN failures INTEGER NOT NULL DEFAULT 0;
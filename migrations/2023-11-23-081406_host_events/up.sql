@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE host_events (
+    id BIGINT PRIMARY KEY NOT NULL,
+    host_id BIGINT NOT NULL,
+    -- NULL when the host was created
+    from_state SMALLINT,
+    state SMALLINT NOT NULL,
+    reason TEXT NOT NULL,
+    at DATETIME NOT NULL,
+
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX host_events_host_id_at ON host_events (host_id, at);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- json of the last facts the envoy reported
+ALTER TABLE hosts ADD COLUMN facts TEXT;
@@ -1,9 +1,26 @@
 #!/bin/bash
+# Description: Push a new envoy binary to the operator.
+# With --upgrade the operator also redistributes it to every host.
+#
 set -e
 set -x
 
 host="root@10.0.20.1"
 
+upgrade=false
+for arg in "$@"; do
+    case $arg in
+        --upgrade) upgrade=true ;;
+        *) echo "unknown argument: $arg" >&2; exit 1 ;;
+    esac
+done
+
+# the operator's http port, later config files override earlier ones like in the operator
+http_port() {
+    awk -F' *= *' '/^\[/ { section = $0 } section == "[http_server]" && $1 == "port" { print $2 }' \
+        configs/default.toml configs/beta.toml configs/release.toml 2>/dev/null | tail -n 1
+}
+
 #############################
 
 # build
@@ -12,3 +29,8 @@ cargo build -p av1-envoy --release
 built_out_path=./target/release/av1-envoy
 target_path="/var/lib/av1-operator/bin/av1-envoy"
 scp -O $built_out_path ${host}:${target_path} >/dev/null 2>&1
+# redistribute to every host
+if $upgrade; then
+    port=$(http_port)
+    curl -sf -X POST -H 'content-type: application/json' -d '{}' "http://${host#*@}:${port}/api/operator/upgrade_envoys"
+fi
@@ -0,0 +1,47 @@
+//! Build outputs packed as `<sha256>.tar.gz` under the artifact dir, so a version keeps
+//! pointing at exactly what was built no matter what the code dir holds later.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tokio::fs;
+use utils::async_cmd;
+
+use crate::{host::files::sha256_file, settings::get_settings};
+
+pub fn artifact_path(sha256: &str) -> PathBuf {
+    get_settings().data_dir.artifact_dir().join(format!("{}.tar.gz", sha256))
+}
+
+/// Pack everything in `dir` into the store and return the sha256 of the tarball.
+///
+/// `tmp_name` must be unique among concurrent packs.
+pub async fn pack(dir: &Path, tmp_name: &str) -> Result<String> {
+    let tmp_path = get_settings().data_dir.artifact_dir().join(format!("{}.tmp", tmp_name));
+    // no timestamps, owners or directory order end up in the tarball, so the same output
+    // always gets the same sha256
+    async_cmd!(
+        "tar",
+        "--sort=name",
+        "--mtime=@0",
+        "--owner=0",
+        "--group=0",
+        "--numeric-owner",
+        "--use-compress-program=gzip -n",
+        "-cf",
+        tmp_path,
+        "-C",
+        dir,
+        "."
+    );
+
+    let sha256 = sha256_file(&tmp_path).await?;
+    let path = artifact_path(&sha256);
+    if fs::try_exists(&path).await? {
+        fs::remove_file(&tmp_path).await?;
+    } else {
+        fs::rename(&tmp_path, &path).await.context("move artifact into store")?;
+    }
+
+    Ok(sha256)
+}
@@ -0,0 +1,469 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io,
+    path::Path,
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use chrono::NaiveDateTime;
+use diesel::{deserialize::FromSqlRow, expression::AsExpression, sql_types::SmallInt};
+use futures::{io::BufReader, stream, stream::BoxStream, AsyncBufReadExt, AsyncRead, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+    sync::watch,
+    task::AbortHandle,
+};
+use tracing::{info, warn};
+use utils::{
+    diesel_enum, id_new_type,
+    macros::async_cmd::async_process::{Command, Stdio},
+};
+
+use crate::{
+    repositry::{self, application::AppVersionPo},
+    settings::get_settings,
+};
+
+use super::{artifact, AppId, Application};
+
+id_new_type!(BuildId);
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Build {
+    pub id: BuildId,
+    pub app_id: AppId,
+    /// the ref requested by user, may be a branch or tag
+    pub hash: String,
+    /// the resolved commit, known once the checkout is done
+    pub commit: Option<String>,
+    pub state: BuildState,
+    pub exit_code: Option<i32>,
+    pub started_at: Option<NaiveDateTime>,
+    pub ended_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = SmallInt)]
+#[repr(i16)]
+pub enum BuildState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+diesel_enum!(BuildState, max = BuildState::Cancelled as i16);
+
+impl Build {
+    fn new(app_id: AppId, hash: String) -> Self {
+        Self {
+            id: BuildId::next_id(),
+            app_id,
+            hash,
+            commit: None,
+            state: BuildState::Queued,
+            exit_code: None,
+            started_at: None,
+            ended_at: None,
+            created_at: now(),
+        }
+    }
+}
+
+fn now() -> NaiveDateTime {
+    chrono::Utc::now().naive_utc()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildAppParams {
+    pub app_id: AppId,
+    /// commit hash, branch or tag to build
+    pub hash: String,
+    /// overrides the stored build.sh for this build only
+    #[serde(default)]
+    pub build_script: Option<String>,
+}
+
+/// Queue a build of the app and return immediately.
+///
+/// Builds of the same app share one code dir, so they run one after another.
+pub async fn build_app(params: BuildAppParams) -> Result<BuildId> {
+    let BuildAppParams {
+        app_id,
+        hash,
+        build_script,
+    } = params;
+    // git would take it for an option
+    ensure!(!hash.is_empty() && !hash.starts_with('-'), "invalid ref {:?}", hash);
+    let conn = &mut repositry::db_conn().await?;
+    let app = repositry::application::find(app_id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("app not found"))?;
+
+    let build = Build::new(app.id, hash);
+    repositry::build::save(&build, conn).await?;
+
+    let id = build.id;
+    let log = Arc::new(LiveLog::new());
+    let mut builds = running_builds().lock().unwrap();
+    let job = tokio::spawn(run_build(app, build, build_script, log.clone()));
+    builds.insert(
+        id,
+        RunningBuild {
+            app_id,
+            job: job.abort_handle(),
+            log,
+        },
+    );
+
+    Ok(id)
+}
+
+pub async fn cancel_build(id: BuildId) -> Result<()> {
+    let Some(running) = running_builds().lock().unwrap().remove(&id) else {
+        bail!("build is not running");
+    };
+    running.job.abort();
+
+    let conn = &mut repositry::db_conn().await?;
+    let build = repositry::build::find(id, conn).await?.context("build not found")?;
+    repositry::build::finish_unfinished(id, BuildState::Cancelled, conn).await?;
+
+    let app = repositry::application::find(build.app_id, conn).await?.context("app not found")?;
+    let log_path = get_settings().data_dir.app_dir(&app.name).build_log_path(id);
+    if let Ok(mut log) = BuildLog::open(&log_path, running.log.clone()).await {
+        log.line("build cancelled").await?;
+    }
+    running.log.finish();
+    info!(%id, "build cancelled");
+
+    Ok(())
+}
+
+pub async fn build_log(id: BuildId) -> Result<String> {
+    let conn = &mut repositry::db_conn().await?;
+    let build = repositry::build::find(id, conn).await?.context("build not found")?;
+    let app = repositry::application::find(build.app_id, conn).await?.context("app not found")?;
+
+    let log_path = get_settings().data_dir.app_dir(&app.name).build_log_path(id);
+    if build.state == BuildState::Queued && !fs::try_exists(&log_path).await? {
+        return Ok(String::new());
+    }
+    let log = fs::read_to_string(&log_path).await.context("read build log")?;
+    Ok(log)
+}
+
+/// Follow the log of a build: what was already produced is replayed first, then new lines
+/// are yielded as they come until the build finishes.
+pub async fn follow_build_log(id: BuildId) -> Result<BoxStream<'static, String>> {
+    let running = running_builds().lock().unwrap().get(&id).map(|b| b.log.clone());
+    if let Some(log) = running {
+        return Ok(log.follow().boxed());
+    }
+
+    let log = build_log(id).await?;
+    let lines: Vec<_> = log.lines().map(ToString::to_string).collect();
+    Ok(stream::iter(lines).boxed())
+}
+
+struct RunningBuild {
+    app_id: AppId,
+    job: AbortHandle,
+    log: Arc<LiveLog>,
+}
+
+fn running_builds() -> &'static Mutex<HashMap<BuildId, RunningBuild>> {
+    static BUILDS: OnceLock<Mutex<HashMap<BuildId, RunningBuild>>> = OnceLock::new();
+    BUILDS.get_or_init(Default::default)
+}
+
+/// A build of the app runs or waits for the code dir
+pub(crate) fn is_building(app_id: AppId) -> bool {
+    running_builds().lock().unwrap().values().any(|b| b.app_id == app_id)
+}
+
+fn app_lock(app_id: AppId) -> Arc<tokio::sync::Mutex<()>> {
+    static LOCKS: OnceLock<Mutex<HashMap<AppId, Arc<tokio::sync::Mutex<()>>>>> = OnceLock::new();
+    let mut locks = LOCKS.get_or_init(Default::default).lock().unwrap();
+    locks.entry(app_id).or_default().clone()
+}
+
+async fn run_build(app: Application, mut build: Build, script: Option<String>, log: Arc<LiveLog>) {
+    let lock = app_lock(app.id);
+    let _guard = lock.lock().await;
+
+    if let Err(err) = app.build(&mut build, script, log.clone()).await {
+        warn!(?err, id = %build.id, "build failed");
+        // the build may have failed before its state was ever set
+        let finished = async {
+            let conn = &mut repositry::db_conn().await?;
+            repositry::build::finish_unfinished(build.id, BuildState::Failed, conn).await
+        };
+        if let Err(err) = finished.await {
+            warn!(?err, id = %build.id, "mark build failed");
+        }
+    }
+    running_builds().lock().unwrap().remove(&build.id);
+    log.finish();
+}
+
+impl Application {
+    /// Checkout the requested ref in the code dir and run the build script there.
+    ///
+    /// The script gets the (emptied) build output dir as `$1`, and should put everything
+    /// that needs to be installed into it.
+    async fn build(&self, build: &mut Build, script: Option<String>, live: Arc<LiveLog>) -> Result<()> {
+        let app_dir = get_settings().data_dir.app_dir(&self.name);
+        fs::create_dir_all(app_dir.build_log_dir()).await?;
+        let mut log = BuildLog::open(&app_dir.build_log_path(build.id), live).await?;
+
+        build.state = BuildState::Running;
+        build.started_at = Some(now());
+        let conn = &mut repositry::db_conn().await?;
+        repositry::build::update(build, conn).await?;
+
+        let result = self.run_build_steps(build, script, &mut log).await;
+        build.ended_at = Some(now());
+        match &result {
+            Ok(_) => {
+                build.state = BuildState::Succeeded;
+                log.line("build succeeded").await?;
+            }
+            Err(err) => {
+                build.state = BuildState::Failed;
+                log.line(&format!("build failed: {:#}", err)).await?;
+            }
+        }
+        let conn = &mut repositry::db_conn().await?;
+        repositry::build::update(build, conn).await?;
+
+        result
+    }
+
+    async fn run_build_steps(&self, build: &mut Build, script: Option<String>, log: &mut BuildLog) -> Result<()> {
+        let app_dir = get_settings().data_dir.app_dir(&self.name);
+        let code_dir = app_dir.code_dir();
+
+        macro_rules! step {
+            ($program:literal, $($args:expr),+ $(,)?) => {{
+                let status = log.run(&code_dir, $program, [$(OsStr::new(&$args)),+]).await?;
+                build.exit_code = status.code();
+                ensure!(status.success(), "`{}` exited with {}", $program, status);
+            }};
+        }
+
+        step!("git", "fetch", "--all", "--tags");
+        let commit = resolve_commit(&code_dir, &build.hash).await?;
+        log.line(&format!("{} is {}", build.hash, commit)).await?;
+        step!("git", "checkout", "--force", "--detach", commit);
+        build.commit = Some(commit.clone());
+
+        let out_dir = app_dir.build_out_dir();
+        if fs::try_exists(&out_dir).await? {
+            fs::remove_dir_all(&out_dir).await.context("clean build out dir")?;
+        }
+        fs::create_dir_all(&out_dir).await.context("create build out dir")?;
+
+        let script = match script {
+            Some(script) => script,
+            None => fs::read_to_string(app_dir.build_script_path()).await.context("read build script")?,
+        };
+        step!("bash", "-c", script, "build.sh", out_dir);
+
+        let mut entries = fs::read_dir(&out_dir).await?;
+        ensure!(
+            entries.next_entry().await?.is_some(),
+            "build script produced nothing in {}",
+            out_dir.display()
+        );
+
+        log.line("$ packing build output").await?;
+        let artifact = artifact::pack(&out_dir, &build.id.to_string()).await.context("pack build output")?;
+        log.line(&format!("artifact sha256: {}", artifact)).await?;
+
+        let conn = &mut repositry::db_conn().await?;
+        let version = AppVersionPo {
+            hash: commit.into(),
+            app_id: self.id,
+            artifact: Some(artifact.into()),
+        };
+        repositry::application::new_version(version, conn).await?;
+
+        Ok(())
+    }
+}
+
+/// The commit `git_ref` points at after a fetch. A branch is taken from `origin`, the local one
+/// is whatever the clone left behind.
+async fn resolve_commit(code_dir: &Path, git_ref: &str) -> Result<String> {
+    for candidate in [format!("origin/{}", git_ref), git_ref.to_string()] {
+        let out = Command::new("git")
+            .current_dir(code_dir)
+            .args(["rev-parse", "--verify", "--quiet", "--end-of-options"])
+            .arg(format!("{}^{{commit}}", candidate))
+            .output()
+            .await
+            .context("run git rev-parse")?;
+        if out.status.success() {
+            let commit = String::from_utf8(out.stdout).context("commit hash is not utf-8")?;
+            return Ok(commit.trim().to_string());
+        }
+    }
+    bail!("{} is no commit, branch or tag", git_ref)
+}
+
+/// Lines of a build that is still running, kept in memory so that clients connecting
+/// late get the whole log.
+struct LiveLog {
+    lines: Mutex<Vec<String>>,
+    finished: AtomicBool,
+    /// notifies followers about new lines
+    version: watch::Sender<()>,
+}
+
+impl LiveLog {
+    fn new() -> Self {
+        Self {
+            lines: Default::default(),
+            finished: AtomicBool::new(false),
+            version: watch::channel(()).0,
+        }
+    }
+
+    fn push(&self, line: String) {
+        self.lines.lock().unwrap().push(line);
+        self.version.send_replace(());
+    }
+
+    fn finish(&self) {
+        self.finished.store(true, Ordering::Release);
+        self.version.send_replace(());
+    }
+
+    fn follow(self: Arc<Self>) -> impl Stream<Item = String> {
+        let rx = self.version.subscribe();
+        stream::unfold((self, rx, 0), |(log, mut rx, next)| async move {
+            loop {
+                rx.borrow_and_update();
+                let finished = log.finished.load(Ordering::Acquire);
+                let lines = log.lines.lock().unwrap()[next..].to_vec();
+                if !lines.is_empty() {
+                    let next = next + lines.len();
+                    return Some((stream::iter(lines), (log, rx, next)));
+                }
+                if finished || rx.changed().await.is_err() {
+                    return None;
+                }
+            }
+        })
+        .flatten()
+    }
+}
+
+/// The combined stdout and stderr of every step of a build
+struct BuildLog {
+    file: File,
+    live: Arc<LiveLog>,
+}
+
+impl BuildLog {
+    async fn open(path: &Path, live: Arc<LiveLog>) -> Result<Self> {
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .context("open build log")?;
+        Ok(Self { file, live })
+    }
+
+    async fn line(&mut self, line: &str) -> Result<()> {
+        self.file.write_all(line.as_bytes()).await?;
+        self.file.write_all(b"\n").await?;
+        self.live.push(line.to_string());
+        Ok(())
+    }
+
+    async fn run<I, S>(&mut self, pwd: &Path, program: &str, args: I) -> Result<ExitStatus>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut cmd = Command::new(program);
+        cmd.current_dir(pwd);
+        let mut line = format!("$ {}", program);
+        for arg in args {
+            let arg = arg.as_ref();
+            line += " ";
+            line += &arg.to_string_lossy();
+            cmd.arg(arg);
+        }
+        self.line(&line).await?;
+
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        cmd.kill_on_drop(true);
+        let mut child = cmd.spawn().with_context(|| format!("spawn {}", program))?;
+
+        let stdout = lossy_lines(child.stdout.take().context("no stdout")?);
+        let stderr = lossy_lines(child.stderr.take().context("no stderr")?);
+        let output = stream::select(stdout, stderr);
+        futures::pin_mut!(output);
+        while let Some(line) = output.next().await {
+            self.line(&line?).await?;
+        }
+
+        let status = child.status().await?;
+        Ok(status)
+    }
+}
+
+/// Build output is not guaranteed to be utf-8, which should not fail the build
+fn lossy_lines<R>(reader: R) -> impl Stream<Item = io::Result<String>>
+where
+    R: AsyncRead + Unpin,
+{
+    stream::unfold(BufReader::new(reader), |mut reader| async move {
+        let mut buf = Vec::new();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => None,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf).trim_end_matches(['\r', '\n']).to_string();
+                Some((Ok(line), reader))
+            }
+            Err(err) => Some((Err(err), reader)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use utils::async_cmd;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn t_bash() -> Result<()> {
+        let cwd = &std::env::current_dir()?;
+        let script = r#"
+            set -e
+            echo "hello"
+            echo "world"
+        "#;
+        let out = async_cmd!(pwd = cwd; "bash", "-c", script);
+        assert_eq!(String::from_utf8(out.stdout)?, "hello\nworld\n");
+        Ok(())
+    }
+}
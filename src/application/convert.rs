@@ -1,6 +1,21 @@
-use crate::repositry::application::{AppVersionPo, ApplicaionPo};
+use std::borrow::Cow;
 
-use super::{AppVersioned, Application};
+use anyhow::Context;
+
+use crate::repositry::{
+    app_instance::AppInstancePo,
+    application::{AppVersionPo, ApplicaionPo},
+    build::BuildPo,
+    deployment::{DeploymentHostPo, DeploymentPo},
+    desired_state::DesiredStatePo,
+};
+
+use super::{
+    deploy::{Deployment, HostDeployment},
+    instance::AppInstance,
+    reconcile::DesiredState,
+    AppVersioned, Application, Build,
+};
 
 impl<'a> From<&'a Application> for ApplicaionPo<'a> {
     fn from(value: &'a Application) -> Self {
@@ -25,11 +40,200 @@ impl TryFrom<(ApplicaionPo<'static>, Vec<AppVersionPo<'static>>)> for Applicatio
             versions: versions
                 .into_iter()
                 .map(|v| {
-                    let AppVersionPo { hash, app_id: _ } = v;
-                    AppVersioned { hash: hash.into_owned() }
+                    let AppVersionPo { hash, app_id: _, artifact } = v;
+                    AppVersioned {
+                        hash: hash.into_owned(),
+                        artifact: artifact.map(Cow::into_owned),
+                    }
                 })
                 .collect(),
         };
         Ok(app)
     }
 }
+
+impl<'a> From<&'a Build> for BuildPo<'a> {
+    fn from(value: &'a Build) -> Self {
+        BuildPo {
+            id: value.id,
+            app_id: value.app_id,
+            hash: (&value.hash).into(),
+            commit_hash: value.commit.as_ref().map(Into::into),
+            state: value.state,
+            exit_code: value.exit_code,
+            started_at: value.started_at,
+            ended_at: value.ended_at,
+            created_at: value.created_at,
+        }
+    }
+}
+
+impl TryFrom<BuildPo<'static>> for Build {
+    type Error = anyhow::Error;
+
+    fn try_from(value: BuildPo<'static>) -> Result<Self, Self::Error> {
+        let BuildPo {
+            id,
+            app_id,
+            hash,
+            commit_hash,
+            state,
+            exit_code,
+            started_at,
+            ended_at,
+            created_at,
+        } = value;
+
+        Ok(Build {
+            id,
+            app_id,
+            hash: hash.into_owned(),
+            commit: commit_hash.map(Cow::into_owned),
+            state,
+            exit_code,
+            started_at,
+            ended_at,
+            created_at,
+        })
+    }
+}
+
+impl<'a> From<&'a Deployment> for (DeploymentPo<'a>, Vec<DeploymentHostPo<'a>>) {
+    fn from(value: &'a Deployment) -> Self {
+        let deployment = DeploymentPo {
+            id: value.id,
+            app_id: value.app_id,
+            hash: (&value.hash).into(),
+            triggered_by: (&value.triggered_by).into(),
+            rollback_of: value.rollback_of,
+            strategy: serde_json::to_string(&value.strategy).unwrap_or_default().into(),
+            state: value.state,
+            started_at: value.started_at,
+            ended_at: value.ended_at,
+        };
+        let hosts = value
+            .hosts
+            .iter()
+            .map(|h| DeploymentHostPo {
+                deployment_id: value.id,
+                host_id: h.host_id,
+                state: h.state,
+                install_dir: h.install_dir.as_ref().map(Into::into),
+                exit_code: h.exit_code,
+                stdout: (&h.stdout).into(),
+                stderr: (&h.stderr).into(),
+                error: h.error.as_ref().map(Into::into),
+            })
+            .collect();
+        (deployment, hosts)
+    }
+}
+
+impl TryFrom<(DeploymentPo<'static>, Vec<DeploymentHostPo<'static>>)> for Deployment {
+    type Error = anyhow::Error;
+
+    fn try_from(value: (DeploymentPo<'static>, Vec<DeploymentHostPo<'static>>)) -> Result<Self, Self::Error> {
+        let (
+            DeploymentPo {
+                id,
+                app_id,
+                hash,
+                triggered_by,
+                rollback_of,
+                strategy,
+                state,
+                started_at,
+                ended_at,
+            },
+            hosts,
+        ) = value;
+
+        let hosts = hosts
+            .into_iter()
+            .map(|h| HostDeployment {
+                host_id: h.host_id,
+                state: h.state,
+                install_dir: h.install_dir.map(Cow::into_owned),
+                exit_code: h.exit_code,
+                stdout: h.stdout.into_owned(),
+                stderr: h.stderr.into_owned(),
+                error: h.error.map(Cow::into_owned),
+            })
+            .collect();
+
+        Ok(Deployment {
+            id,
+            app_id,
+            hash: hash.into_owned(),
+            triggered_by: triggered_by.into_owned(),
+            rollback_of,
+            strategy: serde_json::from_str(&strategy).context("invalid deployment strategy")?,
+            state,
+            hosts,
+            started_at,
+            ended_at,
+        })
+    }
+}
+
+impl<'a> From<&'a AppInstance> for AppInstancePo<'a> {
+    fn from(value: &'a AppInstance) -> Self {
+        AppInstancePo {
+            app_id: value.app_id,
+            host_id: value.host_id,
+            hash: (&value.hash).into(),
+            install_dir: value.install_dir.as_ref().map(Into::into),
+            state: value.state,
+            reported_hash: value.reported_hash.as_ref().map(Into::into),
+            reported_at: value.reported_at,
+        }
+    }
+}
+
+impl TryFrom<AppInstancePo<'static>> for AppInstance {
+    type Error = anyhow::Error;
+
+    fn try_from(value: AppInstancePo<'static>) -> Result<Self, Self::Error> {
+        let AppInstancePo {
+            app_id,
+            host_id,
+            hash,
+            install_dir,
+            state,
+            reported_hash,
+            reported_at,
+        } = value;
+
+        Ok(AppInstance {
+            app_id,
+            host_id,
+            hash: hash.into_owned(),
+            install_dir: install_dir.map(Cow::into_owned),
+            state,
+            reported_hash: reported_hash.map(Cow::into_owned),
+            reported_at,
+        })
+    }
+}
+
+impl<'a> From<&'a DesiredState> for DesiredStatePo<'a> {
+    fn from(value: &'a DesiredState) -> Self {
+        DesiredStatePo {
+            app_id: value.app_id,
+            hash: (&value.hash).into(),
+            group_id: value.group_id,
+        }
+    }
+}
+
+impl TryFrom<DesiredStatePo<'static>> for DesiredState {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DesiredStatePo<'static>) -> Result<Self, Self::Error> {
+        Ok(DesiredState {
+            app_id: value.app_id,
+            hash: value.hash.into_owned(),
+            group_id: value.group_id,
+        })
+    }
+}
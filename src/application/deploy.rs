@@ -0,0 +1,487 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use chrono::NaiveDateTime;
+use diesel::{deserialize::FromSqlRow, expression::AsExpression, sql_types::SmallInt};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::oneshot, time::Instant};
+use tracing::{info, warn};
+use utils::{diesel_enum, id_new_type};
+use volo_gen::av1::operator::{DeployReq, HealthCheckReq};
+
+use crate::{
+    host::{files, Host, HostId},
+    repositry,
+    settings::get_settings,
+};
+
+use super::{artifact::artifact_path, instance, AppId, Application};
+
+/// How long a freshly installed host may take to report healthy
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(30);
+const HEALTH_INTERVAL: Duration = Duration::from_secs(2);
+/// Where artifacts are uploaded to on the host, the envoy removes them once unpacked
+const REMOTE_ARTIFACT_DIR: &str = "/var/cache/av1-envoy/artifacts";
+
+id_new_type!(DeploymentId);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Deployment {
+    pub id: DeploymentId,
+    pub app_id: AppId,
+    pub hash: String,
+    pub triggered_by: String,
+    /// the deployment this one rolled back
+    pub rollback_of: Option<DeploymentId>,
+    pub strategy: DeployStrategy,
+    pub state: DeploymentState,
+    pub hosts: Vec<HostDeployment>,
+    pub started_at: NaiveDateTime,
+    pub ended_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = SmallInt)]
+#[repr(i16)]
+pub enum DeploymentState {
+    Running,
+    /// every host installed the version
+    Succeeded,
+    /// a batch failed and the rollout was halted
+    Failed,
+    /// the canary batch is done, the rest waits for an approval
+    WaitingApproval,
+    Cancelled,
+}
+
+diesel_enum!(DeploymentState, max = DeploymentState::Cancelled as i16);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DeployStrategy {
+    #[default]
+    AllAtOnce,
+    /// `batch_size` hosts at a time, pausing `pause_secs` between batches
+    #[serde(rename_all = "camelCase")]
+    Rolling {
+        batch_size: usize,
+        #[serde(default)]
+        pause_secs: u64,
+    },
+    /// `hosts` hosts first, the rest after an approval, or once `wait_secs` passed without a rejection
+    #[serde(rename_all = "camelCase")]
+    Canary { hosts: usize, wait_secs: Option<u64> },
+}
+
+impl DeployStrategy {
+    fn validate(&self) -> Result<()> {
+        match *self {
+            DeployStrategy::AllAtOnce => {}
+            DeployStrategy::Rolling { batch_size, .. } => ensure!(batch_size > 0, "batch size must be positive"),
+            DeployStrategy::Canary { hosts, .. } => ensure!(hosts > 0, "canary needs at least one host"),
+        }
+        Ok(())
+    }
+
+    /// Split `count` hosts into the batches deployed one after another
+    fn batches(&self, count: usize) -> Vec<Range<usize>> {
+        let size = match *self {
+            DeployStrategy::AllAtOnce => count,
+            DeployStrategy::Rolling { batch_size, .. } => batch_size,
+            DeployStrategy::Canary { hosts, .. } => {
+                let canary = hosts.min(count);
+                return [0..canary, canary..count].into_iter().filter(|r| !r.is_empty()).collect();
+            }
+        };
+        (0..count)
+            .step_by(size.max(1))
+            .map(|start| start..count.min(start + size))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostDeployment {
+    pub host_id: HostId,
+    pub state: HostDeployState,
+    pub install_dir: Option<String>,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// why the host failed when it was not the install script
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = SmallInt)]
+#[repr(i16)]
+pub enum HostDeployState {
+    Pending,
+    Succeeded,
+    Failed,
+    /// not deployed because the rollout stopped before its batch
+    Skipped,
+}
+
+diesel_enum!(HostDeployState, max = HostDeployState::Skipped as i16);
+
+impl Deployment {
+    fn new(app_id: AppId, hash: String, hosts: Vec<HostId>, strategy: DeployStrategy, triggered_by: String) -> Self {
+        Self {
+            id: DeploymentId::next_id(),
+            app_id,
+            hash,
+            triggered_by,
+            rollback_of: None,
+            strategy,
+            state: DeploymentState::Running,
+            hosts: hosts.into_iter().map(HostDeployment::pending).collect(),
+            started_at: now(),
+            ended_at: None,
+        }
+    }
+
+    fn finish(&mut self, state: DeploymentState) {
+        for host in &mut self.hosts {
+            if host.state == HostDeployState::Pending {
+                host.state = HostDeployState::Skipped;
+            }
+        }
+        self.state = state;
+        self.ended_at = Some(now());
+    }
+}
+
+impl HostDeployment {
+    fn pending(host_id: HostId) -> Self {
+        Self {
+            host_id,
+            state: HostDeployState::Pending,
+            install_dir: None,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            error: None,
+        }
+    }
+}
+
+fn now() -> NaiveDateTime {
+    chrono::Utc::now().naive_utc()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeployParams {
+    pub app_id: AppId,
+    /// an already built commit of the app
+    pub hash: String,
+    pub hosts: Vec<HostId>,
+    #[serde(default)]
+    pub strategy: DeployStrategy,
+    /// recorded in the deployment history, the caller address is used when absent
+    #[serde(default)]
+    pub triggered_by: Option<String>,
+}
+
+/// Start rolling out an app version and return the new deployment right away.
+///
+/// Hosts are deployed batch by batch as the strategy says. A host counts as done once its
+/// install script succeeded and it reported healthy, a failing batch halts the rollout.
+pub async fn deploy(params: DeployParams) -> Result<Deployment> {
+    let DeployParams {
+        app_id,
+        hash,
+        mut hosts,
+        strategy,
+        triggered_by,
+    } = params;
+    ensure!(!hosts.is_empty(), "no target hosts");
+    strategy.validate()?;
+    let mut seen = HashSet::new();
+    hosts.retain(|id| seen.insert(*id));
+
+    let deployment = Deployment::new(app_id, hash, hosts, strategy, triggered_by.unwrap_or_default());
+    start_deployment(deployment).await
+}
+
+/// Redeploy the last successfully deployed version that differs from the latest deployment,
+/// to the hosts of the latest deployment, all at once.
+pub async fn rollback(app_id: AppId, triggered_by: String) -> Result<Deployment> {
+    let conn = &mut repositry::db_conn().await?;
+    let latest = repositry::deployment::latest(app_id, conn)
+        .await?
+        .context("app has never been deployed")?;
+    let target = repositry::deployment::last_succeeded_except(app_id, &latest.hash, conn)
+        .await?
+        .context("no previous successful version to roll back to")?;
+
+    info!(app = %app_id, from = %latest.hash, to = %target.hash, "rolling back");
+    let hosts = latest.hosts.iter().map(|h| h.host_id).collect();
+    let mut deployment = Deployment::new(app_id, target.hash, hosts, DeployStrategy::AllAtOnce, triggered_by);
+    deployment.rollback_of = Some(latest.id);
+    start_deployment(deployment).await
+}
+
+/// Let a canary deployment go on to the remaining hosts, or stop it there
+pub async fn approve_deployment(id: DeploymentId, approved: bool) -> Result<()> {
+    let Some(approval) = approvals().lock().unwrap().remove(&id) else {
+        bail!("deployment is not waiting for approval");
+    };
+    approval.send(approved).ok();
+    info!(%id, approved, "deployment approval");
+    Ok(())
+}
+
+fn approvals() -> &'static Mutex<HashMap<DeploymentId, oneshot::Sender<bool>>> {
+    static APPROVALS: OnceLock<Mutex<HashMap<DeploymentId, oneshot::Sender<bool>>>> = OnceLock::new();
+    APPROVALS.get_or_init(Default::default)
+}
+
+/// What every host of a deployment gets
+struct Rollout {
+    app_id: AppId,
+    targets: Vec<Host>,
+    /// without the artifact, each host gets it uploaded first
+    req: DeployReq,
+    artifact: PathBuf,
+    health_script: String,
+}
+
+async fn start_deployment(deployment: Deployment) -> Result<Deployment> {
+    let conn = &mut repositry::db_conn().await?;
+    let app = repositry::application::find(deployment.app_id, conn)
+        .await?
+        .context("app not found")?;
+    let version = repositry::application::find_version(&deployment.hash, conn)
+        .await?
+        .filter(|v| v.app_id == app.id)
+        .context("version not found")?;
+    let sha256 = version.artifact.context("version has no artifact, rebuild it")?.into_owned();
+
+    let mut targets = Vec::with_capacity(deployment.hosts.len());
+    for h in &deployment.hosts {
+        let host = repositry::host::get(h.host_id, conn).await?.context("host not found")?;
+        targets.push(host);
+    }
+
+    let rollout = Rollout {
+        app_id: app.id,
+        targets,
+        req: app.deploy_req(&deployment.hash, sha256.clone()).await?,
+        artifact: artifact_path(&sha256),
+        health_script: app.health_script().await?,
+    };
+    repositry::deployment::save(&deployment, conn).await?;
+
+    info!(id = %deployment.id, app = %app.name, hash = %deployment.hash, strategy = ?deployment.strategy, "deploying");
+    tokio::spawn(run_deployment(deployment.clone(), rollout));
+    Ok(deployment)
+}
+
+async fn run_deployment(mut deployment: Deployment, rollout: Rollout) {
+    let id = deployment.id;
+    if let Err(err) = rollout.run(&mut deployment).await {
+        warn!(?err, %id, "deployment aborted");
+        deployment.finish(DeploymentState::Failed);
+    }
+    approvals().lock().unwrap().remove(&id);
+
+    if let Err(err) = save_progress(&deployment).await {
+        warn!(?err, %id, "cannot save deployment");
+    }
+    info!(%id, state = ?deployment.state, "deployment finished");
+}
+
+async fn save_progress(deployment: &Deployment) -> Result<()> {
+    let conn = &mut repositry::db_conn().await?;
+    repositry::deployment::update(deployment, conn).await
+}
+
+impl Rollout {
+    async fn run(&self, deployment: &mut Deployment) -> Result<()> {
+        let batches = deployment.strategy.batches(self.targets.len());
+        let count = batches.len();
+        for (i, batch) in batches.into_iter().enumerate() {
+            if i > 0 && !wait_next_batch(deployment).await? {
+                deployment.finish(DeploymentState::Cancelled);
+                return Ok(());
+            }
+
+            info!(id = %deployment.id, batch = i + 1, of = count, "deploying batch");
+            let hosts = &self.targets[batch.clone()];
+            let results = join_all(hosts.iter().map(|host| self.deploy_to_host(host))).await;
+            let failed = results.iter().any(|r| r.state != HostDeployState::Succeeded);
+            deployment.hosts.splice(batch, results);
+
+            if failed {
+                warn!(id = %deployment.id, batch = i + 1, "batch failed, halting the rollout");
+                deployment.finish(DeploymentState::Failed);
+                return Ok(());
+            }
+            save_progress(deployment).await?;
+        }
+
+        deployment.finish(DeploymentState::Succeeded);
+        Ok(())
+    }
+
+    /// Upload the artifact and point the request at it, envoys from before put_file get it inline
+    async fn host_req(&self, host: &Host) -> Result<DeployReq> {
+        let mut req = self.req.clone();
+        let remote = format!("{}/{}.tar.gz", REMOTE_ARTIFACT_DIR, req.artifact_sha256);
+        // the artifact is stored under its sha256, no need to hash it again for every host
+        let uploaded = host
+            .put_file_with_sha256(&self.artifact, &req.artifact_sha256, &remote, 0o600, None)
+            .await;
+        match uploaded {
+            Ok(()) => req.artifact_path = remote.into(),
+            Err(err) if files::unimplemented(&err) => {
+                req.artifact = fs::read(&self.artifact).await.context("read artifact")?.into();
+            }
+            Err(err) => return Err(err.context("upload artifact")),
+        }
+        Ok(req)
+    }
+
+    async fn deploy_to_host(&self, host: &Host) -> HostDeployment {
+        let mut result = HostDeployment::pending(host.id);
+        let deployed = match self.host_req(host).await {
+            Ok(req) => host.client().deploy(req).await.map_err(|status| status.message().to_string()),
+            Err(err) => Err(format!("{:#}", err)),
+        };
+        match deployed {
+            Ok(resp) => {
+                let resp = resp.into_inner();
+                result.state = if resp.exit_code == 0 {
+                    HostDeployState::Succeeded
+                } else {
+                    HostDeployState::Failed
+                };
+                result.install_dir = Some(resp.install_dir.to_string());
+                result.exit_code = Some(resp.exit_code);
+                result.stdout = resp.stdout.to_string();
+                result.stderr = resp.stderr.to_string();
+            }
+            Err(err) => {
+                warn!(%err, host = %host.id, "deploy failed");
+                result.state = HostDeployState::Failed;
+                result.error = Some(err);
+            }
+        }
+
+        if result.state == HostDeployState::Succeeded {
+            if let Err(err) = self.health_gate(host).await {
+                warn!(host = %host.id, %err, "host unhealthy after deploy");
+                result.state = HostDeployState::Failed;
+                result.error = Some(err);
+            }
+        }
+        if let Err(err) = instance::record_deploy(self.app_id, &self.req.version, &result).await {
+            warn!(?err, host = %host.id, "cannot record app instance");
+        }
+        result
+    }
+
+    /// Poll the health check until it passes or `HEALTH_TIMEOUT` runs out
+    async fn health_gate(&self, host: &Host) -> Result<(), String> {
+        let req = HealthCheckReq {
+            app: self.req.app.clone(),
+            version: self.req.version.clone(),
+            script: self.health_script.clone().into(),
+        };
+        let deadline = Instant::now() + HEALTH_TIMEOUT;
+        loop {
+            let last = match host.client().check_health(req.clone()).await {
+                Ok(resp) if resp.get_ref().healthy => return Ok(()),
+                Ok(resp) => resp.into_inner().output.to_string(),
+                Err(err) => err.message().to_string(),
+            };
+            if Instant::now() >= deadline {
+                return Err(format!("health check failed: {}", last));
+            }
+            tokio::time::sleep(HEALTH_INTERVAL).await;
+        }
+    }
+}
+
+/// Returns false when the rest of the rollout was rejected
+async fn wait_next_batch(deployment: &mut Deployment) -> Result<bool> {
+    match deployment.strategy {
+        DeployStrategy::AllAtOnce => Ok(true),
+        DeployStrategy::Rolling { pause_secs, .. } => {
+            tokio::time::sleep(Duration::from_secs(pause_secs)).await;
+            Ok(true)
+        }
+        DeployStrategy::Canary { wait_secs, .. } => {
+            let (tx, rx) = oneshot::channel();
+            approvals().lock().unwrap().insert(deployment.id, tx);
+            deployment.state = DeploymentState::WaitingApproval;
+            save_progress(deployment).await?;
+            info!(id = %deployment.id, ?wait_secs, "canary batch done, waiting for approval");
+
+            let approved = match wait_secs {
+                Some(secs) => tokio::select! {
+                    approved = rx => approved.unwrap_or(false),
+                    _ = tokio::time::sleep(Duration::from_secs(secs)) => true,
+                },
+                None => rx.await.unwrap_or(false),
+            };
+            approvals().lock().unwrap().remove(&deployment.id);
+            deployment.state = DeploymentState::Running;
+            Ok(approved)
+        }
+    }
+}
+
+impl Application {
+    async fn deploy_req(&self, hash: &str, sha256: String) -> Result<DeployReq> {
+        let install_script_path = get_settings().data_dir.app_dir(&self.name).install_script_path();
+        let install_script = fs::read_to_string(install_script_path).await.context("read install script")?;
+
+        Ok(DeployReq {
+            app: self.name.clone().into(),
+            version: hash.to_string().into(),
+            artifact: Default::default(),
+            artifact_sha256: sha256.into(),
+            install_script: install_script.into(),
+            artifact_path: Default::default(),
+        })
+    }
+
+    /// Empty when the app has no health.sh, the health check then only checks the envoy is up
+    async fn health_script(&self) -> Result<String> {
+        let path = get_settings().data_dir.app_dir(&self.name).health_script_path();
+        if !fs::try_exists(&path).await? {
+            return Ok(String::new());
+        }
+        fs::read_to_string(path).await.context("read health script")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::DeployStrategy;
+
+    #[test]
+    fn t_batches() {
+        assert_eq!(DeployStrategy::AllAtOnce.batches(3), vec![0..3]);
+        let rolling = DeployStrategy::Rolling {
+            batch_size: 2,
+            pause_secs: 0,
+        };
+        assert_eq!(rolling.batches(5), vec![0..2, 2..4, 4..5]);
+        let canary = DeployStrategy::Canary { hosts: 1, wait_secs: None };
+        assert_eq!(canary.batches(4), vec![0..1, 1..4]);
+        assert_eq!(canary.batches(1), vec![0..1]);
+    }
+}
@@ -1,18 +1,60 @@
-use actix_web::web::{self, Json};
+use actix_files::NamedFile;
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::{self, Json, Query},
+    HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use futures::{stream, StreamExt};
 
 use crate::{
-    http::{ApiResponse, ApiResult, Pagination},
+    host::{
+        http_enpoint::journal_sse,
+        journal::JournalParams,
+        service::{ServiceAction, ServiceStatus},
+        HostId,
+    },
+    http::{sse, ApiError, ApiResponse, ApiResult, Pagination, SseEvent},
     repositry::{self, PageList},
 };
 
-use super::{create_app as create_app_inner, Application, CreateAppParams};
+use super::{
+    artifact::artifact_path,
+    build_app as build_app_inner, build_log as build_log_inner, cancel_build as cancel_build_inner, create_app as create_app_inner,
+    deploy::{
+        approve_deployment as approve_deployment_inner, deploy as deploy_inner, rollback as rollback_inner, DeployParams, Deployment,
+        DeploymentId,
+    },
+    follow_build_log,
+    instance::{service_unit, AppInstance},
+    reconcile::{reconcile_all, reconcile_status as reconcile_status_of, reconcile_statuses, DesiredState, ReconcileStatus},
+    AppId, Application, Build, BuildAppParams, BuildId, CreateAppParams,
+};
 
 pub fn config(cfg: &mut actix_web::web::ServiceConfig) {
-    cfg.service(
-        web::scope("/api/operator")
-            .route("app_list", web::post().to(app_list))
-            .route("create_app", web::get().to(create_app)),
-    );
+    cfg.route("app_list", web::post().to(app_list))
+        .route("create_app", web::get().to(create_app))
+        .route("build_app", web::post().to(build_app))
+        .route("build", web::get().to(build_info))
+        .route("builds", web::post().to(build_list))
+        .route("build_log", web::get().to(build_log))
+        .route("build_log_stream", web::get().to(build_log_stream))
+        .route("cancel_build", web::post().to(cancel_build))
+        .route("artifact", web::get().to(download_artifact))
+        .route("deploy", web::post().to(deploy))
+        .route("deployment", web::get().to(deployment_info))
+        .route("deployments", web::post().to(deployment_list))
+        .route("rollback", web::post().to(rollback))
+        .route("instances", web::get().to(instance_list))
+        .route("instance_service", web::post().to(instance_service))
+        .route("instance_journal", web::get().to(instance_journal))
+        .route("set_desired_state", web::post().to(set_desired_state))
+        .route("delete_desired_state", web::post().to(delete_desired_state))
+        .route("desired_states", web::get().to(desired_states))
+        .route("reconcile", web::post().to(reconcile))
+        .route("reconcile_status", web::get().to(reconcile_status))
+        .route("approve_deployment", web::post().to(approve_deployment))
+        .route("reject_deployment", web::post().to(reject_deployment));
 }
 
 async fn create_app(params: Json<CreateAppParams>) -> ApiResult<()> {
@@ -20,6 +62,295 @@ async fn create_app(params: Json<CreateAppParams>) -> ApiResult<()> {
     ApiResponse::ok(())
 }
 
+async fn build_app(params: Json<BuildAppParams>) -> ApiResult<BuildId> {
+    let id = build_app_inner(params.into_inner()).await?;
+    ApiResponse::ok(id)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildIdParams {
+    id: BuildId,
+}
+
+async fn build_info(params: Query<BuildIdParams>) -> ApiResult<Build> {
+    let BuildIdParams { id } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let build = repositry::build::find(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("build not found"))?;
+
+    ApiResponse::ok(build)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildListParams {
+    app_id: AppId,
+    #[serde(flatten)]
+    page: Pagination,
+}
+
+async fn build_list(params: Json<BuildListParams>) -> ApiResult<PageList<Build>> {
+    let BuildListParams { app_id, page } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let builds = repositry::build::list(app_id, page, conn).await?;
+
+    ApiResponse::ok(builds)
+}
+
+async fn build_log(params: Query<BuildIdParams>) -> ApiResult<String> {
+    let BuildIdParams { id } = params.into_inner();
+    let log = build_log_inner(id).await?;
+    ApiResponse::ok(log)
+}
+
+/// Server-Sent Events: one `data` event per log line, then an `end` event carrying the final build state
+async fn build_log_stream(params: Query<BuildIdParams>) -> Result<HttpResponse, ApiError> {
+    let BuildIdParams { id } = params.into_inner();
+    let lines = follow_build_log(id).await?;
+
+    let end = stream::once(async move {
+        let state = async {
+            let conn = &mut repositry::db_conn().await?;
+            let build = repositry::build::find(id, conn).await?;
+            anyhow::Ok(build.map(|b| format!("{:?}", b.state)))
+        };
+        let state = state.await.ok().flatten().unwrap_or_default();
+        SseEvent::named("end", state)
+    });
+    Ok(sse(lines.map(SseEvent::data).chain(end)))
+}
+
+async fn cancel_build(params: Query<BuildIdParams>) -> ApiResult<()> {
+    let BuildIdParams { id } = params.into_inner();
+    cancel_build_inner(id).await?;
+    ApiResponse::ok(())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionParams {
+    hash: String,
+}
+
+/// The packed build output of an app version, as `<app>-<hash>.tar.gz`
+async fn download_artifact(params: Query<VersionParams>) -> Result<NamedFile, ApiError> {
+    let VersionParams { hash } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let version = repositry::application::find_version(&hash, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("version not found"))?;
+    let sha256 = version
+        .artifact
+        .ok_or_else(|| anyhow::anyhow!("version has no artifact, rebuild it"))?;
+    let app = repositry::application::find(version.app_id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("app not found"))?;
+
+    let file = NamedFile::open_async(artifact_path(&sha256)).await.context("open artifact")?;
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(format!("{}-{}.tar.gz", app.name, hash))],
+    };
+    Ok(file.set_content_disposition(disposition))
+}
+
+fn caller(req: &HttpRequest) -> String {
+    req.connection_info().realip_remote_addr().unwrap_or_default().to_string()
+}
+
+async fn deploy(req: HttpRequest, params: Json<DeployParams>) -> ApiResult<Deployment> {
+    let mut params = params.into_inner();
+    params.triggered_by.get_or_insert_with(|| caller(&req));
+    let deployment = deploy_inner(params).await?;
+    ApiResponse::ok(deployment)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeploymentIdParams {
+    id: DeploymentId,
+}
+
+async fn deployment_info(params: Query<DeploymentIdParams>) -> ApiResult<Deployment> {
+    let DeploymentIdParams { id } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let deployment = repositry::deployment::find(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("deployment not found"))?;
+
+    ApiResponse::ok(deployment)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeploymentListParams {
+    app_id: AppId,
+    #[serde(flatten)]
+    page: Pagination,
+}
+
+/// Deployments of an app, newest first
+async fn deployment_list(params: Json<DeploymentListParams>) -> ApiResult<PageList<Deployment>> {
+    let DeploymentListParams { app_id, page } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let deployments = repositry::deployment::list(app_id, page, conn).await?;
+
+    ApiResponse::ok(deployments)
+}
+
+/// Where the app is installed, one instance per host
+async fn instance_list(params: Query<AppIdParams>) -> ApiResult<Vec<AppInstance>> {
+    let AppIdParams { app_id } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let instances = repositry::app_instance::list_by_app(app_id, conn).await?;
+    ApiResponse::ok(instances)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceServiceParams {
+    app_id: AppId,
+    host_id: HostId,
+    /// only the status is read when absent
+    #[serde(default)]
+    action: Option<ServiceAction>,
+}
+
+/// Control the systemd unit of the app on one host, see [`service_unit`]
+async fn instance_service(params: Json<InstanceServiceParams>) -> ApiResult<ServiceStatus> {
+    let InstanceServiceParams { app_id, host_id, action } = params.into_inner();
+    // not held while the envoy runs systemctl
+    let (app, host) = {
+        let conn = &mut repositry::db_conn().await?;
+        let app = repositry::application::find(app_id, conn)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("app not found"))?;
+        repositry::app_instance::find(app_id, host_id, conn)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("the app is not installed on the host"))?;
+        let host = repositry::host::get(host_id, conn)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("host not found"))?;
+        (app, host)
+    };
+
+    ApiResponse::ok(host.service_control(&service_unit(&app.name), action).await?)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InstanceJournalParams {
+    app_id: AppId,
+    host_id: HostId,
+    since: Option<String>,
+    /// the last 100 lines when neither `since` nor `lines` is given
+    lines: Option<u32>,
+    #[serde(default)]
+    follow: bool,
+}
+
+/// Server-Sent Events of the journal of the app's unit on one host, like `host_journal`
+async fn instance_journal(params: Query<InstanceJournalParams>) -> Result<HttpResponse, ApiError> {
+    let InstanceJournalParams {
+        app_id,
+        host_id,
+        since,
+        lines,
+        follow,
+    } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let app = repositry::application::find(app_id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("app not found"))?;
+    let host = repositry::host::get(host_id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host not found"))?;
+
+    let params = JournalParams {
+        unit: service_unit(&app.name),
+        since,
+        lines,
+        follow,
+    };
+    Ok(journal_sse(host.journal(params).await?))
+}
+
+async fn set_desired_state(params: Json<DesiredState>) -> ApiResult<()> {
+    let desired = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    repositry::application::find(desired.app_id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("app not found"))?;
+    repositry::host_group::find(desired.group_id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host group not found"))?;
+    repositry::desired_state::upsert(&desired, conn).await?;
+    ApiResponse::ok(())
+}
+
+/// Stop reconciling the app, what is installed stays as it is
+async fn delete_desired_state(params: Query<AppIdParams>) -> ApiResult<()> {
+    let AppIdParams { app_id } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    repositry::desired_state::delete(app_id, conn).await?;
+    ApiResponse::ok(())
+}
+
+async fn desired_states() -> ApiResult<Vec<DesiredState>> {
+    let conn = &mut repositry::db_conn().await?;
+    let desired = repositry::desired_state::list(conn).await?;
+    ApiResponse::ok(desired)
+}
+
+/// Run a reconcile round now instead of waiting for the next one
+async fn reconcile() -> ApiResult<Vec<ReconcileStatus>> {
+    let statuses = reconcile_all().await?;
+    ApiResponse::ok(statuses)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconcileStatusParams {
+    #[serde(default)]
+    app_id: Option<AppId>,
+}
+
+/// The last reconcile result of one app, or of every app with a desired state
+async fn reconcile_status(params: Query<ReconcileStatusParams>) -> ApiResult<Vec<ReconcileStatus>> {
+    let ReconcileStatusParams { app_id } = params.into_inner();
+    let statuses = match app_id {
+        Some(app_id) => reconcile_status_of(app_id).into_iter().collect(),
+        None => reconcile_statuses(),
+    };
+    ApiResponse::ok(statuses)
+}
+
+async fn approve_deployment(params: Query<DeploymentIdParams>) -> ApiResult<()> {
+    let DeploymentIdParams { id } = params.into_inner();
+    approve_deployment_inner(id, true).await?;
+    ApiResponse::ok(())
+}
+
+async fn reject_deployment(params: Query<DeploymentIdParams>) -> ApiResult<()> {
+    let DeploymentIdParams { id } = params.into_inner();
+    approve_deployment_inner(id, false).await?;
+    ApiResponse::ok(())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppIdParams {
+    app_id: AppId,
+}
+
+async fn rollback(req: HttpRequest, params: Query<AppIdParams>) -> ApiResult<Deployment> {
+    let AppIdParams { app_id } = params.into_inner();
+    let deployment = rollback_inner(app_id, caller(&req)).await?;
+    ApiResponse::ok(deployment)
+}
+
 pub async fn app_list(params: Json<Pagination>) -> ApiResult<PageList<Application>> {
     let page = params.into_inner();
     let conn = &mut repositry::db_conn().await?;
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use diesel::{deserialize::FromSqlRow, expression::AsExpression, sql_types::SmallInt};
+use serde::Serialize;
+use tracing::debug;
+use utils::diesel_enum;
+use volo_gen::av1::operator::ListInstalledReq;
+
+use crate::{
+    host::{Host, HostId},
+    repositry,
+};
+
+use super::{
+    deploy::{HostDeployState, HostDeployment},
+    AppId,
+};
+
+/// An app on a host, as far as the operator knows
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInstance {
+    pub app_id: AppId,
+    pub host_id: HostId,
+    /// the version last deployed to the host
+    pub hash: String,
+    pub install_dir: Option<String>,
+    pub state: InstanceState,
+    /// the current version the envoy reported at `reported_at`
+    pub reported_hash: Option<String>,
+    pub reported_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = SmallInt)]
+#[repr(i16)]
+pub enum InstanceState {
+    Installed,
+    /// the last deploy to the host failed
+    Failed,
+    /// the host reports another current version than the deployed one
+    Drifted,
+    /// the host reports no current version of the app
+    Missing,
+}
+
+diesel_enum!(InstanceState, max = InstanceState::Missing as i16);
+
+/// The systemd unit an app runs as. The install script is expected to set it up under the app's name.
+pub fn service_unit(app_name: &str) -> String {
+    format!("{}.service", app_name)
+}
+
+/// Remember what a deploy left on the host.
+/// A failed deploy only marks the instance failed, it keeps the version the host had before.
+pub async fn record_deploy(app_id: AppId, hash: &str, result: &HostDeployment) -> Result<()> {
+    let conn = &mut repositry::db_conn().await?;
+    let previous = repositry::app_instance::find(app_id, result.host_id, conn).await?;
+    let instance = match (result.state, previous) {
+        (HostDeployState::Succeeded, previous) => AppInstance {
+            app_id,
+            host_id: result.host_id,
+            hash: hash.to_string(),
+            install_dir: result.install_dir.clone(),
+            state: InstanceState::Installed,
+            reported_hash: previous.as_ref().and_then(|i| i.reported_hash.clone()),
+            reported_at: previous.and_then(|i| i.reported_at),
+        },
+        (_, Some(previous)) => AppInstance {
+            state: InstanceState::Failed,
+            ..previous
+        },
+        // nothing was installed before, the failed version is all there is to remember
+        (_, None) => AppInstance {
+            app_id,
+            host_id: result.host_id,
+            hash: hash.to_string(),
+            install_dir: None,
+            state: InstanceState::Failed,
+            reported_hash: None,
+            reported_at: None,
+        },
+    };
+    repositry::app_instance::upsert(&instance, conn).await
+}
+
+/// Ask the envoy what is installed on the host and compare it with the recorded instances
+pub async fn sync_host_instances(host: &Host) -> Result<Vec<AppInstance>> {
+    let resp = host
+        .client()
+        .list_installed(ListInstalledReq {})
+        .await
+        .context("list installed apps")?;
+    let reported: HashMap<_, _> = resp
+        .into_inner()
+        .apps
+        .into_iter()
+        .map(|app| (app.app.to_string(), app.current.to_string()))
+        .collect();
+
+    let conn = &mut repositry::db_conn().await?;
+    let mut instances = repositry::app_instance::list_by_host(host.id, conn).await?;
+    let now = chrono::Utc::now().naive_utc();
+    for instance in &mut instances {
+        let app = repositry::application::find(instance.app_id, conn)
+            .await?
+            .context("app not found")?;
+        let current = reported.get(&app.name).filter(|v| !v.is_empty()).cloned();
+        instance.state = match &current {
+            None => InstanceState::Missing,
+            Some(current) if *current != instance.hash => InstanceState::Drifted,
+            // the version is there, but a failed health check is not undone by that
+            Some(_) if instance.state == InstanceState::Failed => InstanceState::Failed,
+            Some(_) => InstanceState::Installed,
+        };
+        debug!(host = %host.id, app = %app.name, ?current, state = ?instance.state, "instance reported");
+        instance.reported_hash = current;
+        instance.reported_at = Some(now);
+        repositry::app_instance::upsert(instance, conn).await?;
+    }
+    Ok(instances)
+}
@@ -3,27 +3,32 @@ use std::path::PathBuf;
 use serde::Serialize;
 use utils::id_new_type;
 
+pub mod artifact;
 pub mod convert;
+pub mod deploy;
 pub mod http;
+pub mod instance;
+pub mod reconcile;
 
 id_new_type!(AppId);
 
 #[derive(Serialize)]
 pub struct Application {
-    id: AppId,
-    name: String,
-    git: String,
+    pub id: AppId,
+    pub name: String,
+    pub git: String,
     versions: Vec<AppVersioned>,
 }
 
 #[derive(Serialize)]
 struct AppVersioned {
     hash: String,
+    artifact: Option<String>,
 }
 
 pub use create::*;
 mod create {
-    use anyhow::Result;
+    use anyhow::{ensure, Context, Result};
     use tokio::{
         fs::{self, File},
         io::AsyncWriteExt,
@@ -31,12 +36,12 @@ mod create {
     use utils::async_cmd;
 
     use crate::{
-        application::{AppId, Application},
+        application::{deploy::DeploymentState, is_building, AppId, Application},
         repositry,
         settings::get_settings,
     };
 
-    #[derive(serde::Deserialize)]
+    #[derive(Debug, serde::Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct CreateAppParams {
         pub name: String,
@@ -44,11 +49,14 @@ mod create {
         pub scripts: AppScripts,
     }
 
-    #[derive(serde::Deserialize)]
+    #[derive(Debug, PartialEq, serde::Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct AppScripts {
         pub build: String,
         pub install: String,
+        /// run on a host after install, exit code 0 means the app is healthy
+        #[serde(default)]
+        pub health: Option<String>,
     }
 
     pub async fn create_app(params: CreateAppParams) -> Result<()> {
@@ -63,6 +71,9 @@ mod create {
         file.write_all(scripts.build.as_bytes()).await?;
         let mut file = File::options().create(true).write(true).open(app_dir.join("install.sh")).await?;
         file.write_all(scripts.install.as_bytes()).await?;
+        if let Some(health) = scripts.health {
+            fs::write(app_dir.health_script_path(), health).await?;
+        }
 
         let app = Application {
             id: AppId::next_id(),
@@ -76,6 +87,65 @@ mod create {
 
         Ok(())
     }
+
+    /// The scripts currently stored for the app
+    pub async fn app_scripts(name: &str) -> Result<AppScripts> {
+        let app_dir = get_settings().data_dir.app_dir(name);
+        let health_path = app_dir.health_script_path();
+        let health = match fs::try_exists(&health_path).await? {
+            true => Some(fs::read_to_string(health_path).await?),
+            false => None,
+        };
+        Ok(AppScripts {
+            build: fs::read_to_string(app_dir.build_script_path()).await?,
+            install: fs::read_to_string(app_dir.install_script_path()).await?,
+            health,
+        })
+    }
+
+    /// Point an existing app at another git url and replace its scripts
+    pub async fn update_app(params: CreateAppParams) -> Result<()> {
+        let CreateAppParams { name, git, scripts } = params;
+        let conn = &mut repositry::db_conn().await?;
+        let mut app = repositry::application::find_by_name(&name, conn).await?.context("app not found")?;
+        let app_dir = get_settings().data_dir.app_dir(&name);
+
+        if app.git != git {
+            let code_dir = app_dir.code_dir();
+            async_cmd!(pwd = code_dir; "git", "remote", "set-url", "origin", git);
+            app.git = git;
+            repositry::application::update(&app, conn).await?;
+        }
+
+        fs::write(app_dir.build_script_path(), scripts.build).await?;
+        fs::write(app_dir.install_script_path(), scripts.install).await?;
+        let health_path = app_dir.health_script_path();
+        match scripts.health {
+            Some(health) => fs::write(health_path, health).await?,
+            None if fs::try_exists(&health_path).await? => fs::remove_file(health_path).await?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Forget the app and remove its code, scripts and build logs. Artifacts are kept,
+    /// they are shared by content. Refused while the app is built or deployed
+    pub async fn delete_app(id: AppId) -> Result<()> {
+        let conn = &mut repositry::db_conn().await?;
+        let app = repositry::application::find(id, conn).await?.context("app not found")?;
+        ensure!(!is_building(id), "app {} is being built", app.name);
+        let deploying = repositry::deployment::latest(id, conn)
+            .await?
+            .is_some_and(|d| matches!(d.state, DeploymentState::Running | DeploymentState::WaitingApproval));
+        ensure!(!deploying, "app {} is being deployed", app.name);
+        repositry::application::delete(id, conn).await?;
+
+        let app_dir = get_settings().data_dir.app_dir(&app.name);
+        if fs::try_exists(&*app_dir).await? {
+            fs::remove_dir_all(&*app_dir).await.context("remove app dir")?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, derive_more::Deref, derive_more::AsRef, derive_more::From)]
@@ -97,85 +167,19 @@ impl AppDir {
     fn install_script_path(&self) -> PathBuf {
         self.0.join("install.sh")
     }
-}
-
-mod build {
-    use std::{os::fd::FromRawFd, process::Stdio};
-
-    use anyhow::{Context, Result};
-    use serde::Deserialize;
-    use tokio::io::AsyncReadExt;
-    use utils::{async_cmd, macros::async_cmd::async_process::Command};
-
-    use crate::{
-        application::AppVersioned,
-        repositry::{self, application::AppVersionPo},
-        settings::get_settings,
-    };
-
-    use super::{AppId, Application};
-
-    #[derive(Deserialize)]
-    struct BuildAppParams {
-        app_id: AppId,
-        hash: String,
-        build_script: Option<String>,
-    }
-
-    pub async fn build_app(params: BuildAppParams) -> Result<()> {
-        let conn = &mut repositry::db_conn().await?;
-        let app = repositry::application::find(params.app_id, conn)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("app not found"))?;
 
-        todo!()
+    fn health_script_path(&self) -> PathBuf {
+        self.0.join("health.sh")
     }
 
-    impl Application {
-        async fn build(&self, hash: &str, script: Option<String>) -> Result<String> {
-            let app_dir = get_settings().data_dir.app_dir(&self.name);
-            let code_dir = app_dir.code_dir();
-
-            async_cmd!(pwd = &code_dir; "git", "checkout", hash, code_dir);
-            let out = if let Some(script) = script {
-                async_cmd!(pwd = &code_dir; "bash", "-c", script)
-            } else {
-                let build_script_path = app_dir.build_script_path();
-                let mut file = tokio::fs::File::open(&build_script_path).await.context("open build script")?;
-                let mut script = String::new();
-                file.read_to_string(&mut script).await.context("read build script")?;
-                async_cmd!(pwd = &code_dir; "bash", "-c", script)
-            };
-
-            let out = String::from_utf8(out.stdout).context("output is not utf-8")?;
-
-            let conn = &mut repositry::db_conn().await?;
-            let version = AppVersionPo {
-                hash: hash.into(),
-                app_id: self.id,
-            };
-            repositry::application::new_version(version, conn).await?;
-
-            Ok(out)
-        }
+    fn build_log_dir(&self) -> PathBuf {
+        self.0.join("logs")
     }
 
-    #[tokio::test]
-    async fn t_bash() -> Result<()> {
-        let cwd = &std::env::current_dir().unwrap();
-        let script = r#"
-            set -e
-            set -x
-
-            echo "hello"
-            echo "world"
-            # ls -al
-        "#;
-        let cmd = Command::new("program");
-
-        let out = async_cmd!(pwd = cwd; "bash", "-c", script);
-        println!("{}", String::from_utf8(out.stdout).unwrap());
-        println!("{}", String::from_utf8(out.stderr).unwrap());
-        Ok(())
+    fn build_log_path(&self, id: BuildId) -> PathBuf {
+        self.build_log_dir().join(format!("build-{}.log", id))
     }
 }
+
+pub use build::*;
+mod build;
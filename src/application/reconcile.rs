@@ -0,0 +1,300 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use crate::{
+    host::{group::HostGroupId, Host, HostId, HostState},
+    repositry,
+    settings::get_settings,
+};
+
+use super::{
+    deploy::{self, DeployParams, DeployStrategy, DeploymentId, DeploymentState},
+    instance::{sync_host_instances, AppInstance, InstanceState},
+    AppId,
+};
+
+/// App `app_id` should run version `hash` on every host of group `group_id`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DesiredState {
+    pub app_id: AppId,
+    pub hash: String,
+    pub group_id: HostGroupId,
+}
+
+/// The outcome of the last reconcile round of an app
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconcileStatus {
+    pub app_id: AppId,
+    pub hash: String,
+    pub group_id: HostGroupId,
+    pub state: ReconcileState,
+    /// hosts of the group without the app
+    pub missing: Vec<HostId>,
+    /// hosts of the group with another version
+    pub stale: Vec<HostId>,
+    /// hosts of the group where the last deploy of the version failed. They are not retried
+    /// by the reconciler, a manual deploy that succeeds brings them back
+    pub failed: Vec<HostId>,
+    /// hosts outside the group that still have the app, only reported
+    pub extra: Vec<HostId>,
+    /// hosts of the group that are not running or whose envoy could not be asked
+    pub unreachable: Vec<HostId>,
+    /// the deployment converging the app
+    pub deployment: Option<DeploymentId>,
+    pub error: Option<String>,
+    pub checked_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ReconcileState {
+    Converged,
+    Converging,
+    Error,
+}
+
+const TRIGGERED_BY: &str = "reconciler";
+
+fn statuses() -> &'static Mutex<HashMap<AppId, ReconcileStatus>> {
+    static STATUSES: OnceLock<Mutex<HashMap<AppId, ReconcileStatus>>> = OnceLock::new();
+    STATUSES.get_or_init(Default::default)
+}
+
+pub fn reconcile_status(app_id: AppId) -> Option<ReconcileStatus> {
+    statuses().lock().unwrap().get(&app_id).cloned()
+}
+
+pub fn reconcile_statuses() -> Vec<ReconcileStatus> {
+    statuses().lock().unwrap().values().cloned().collect()
+}
+
+/// Run a reconcile round every `interval`, a zero interval leaves reconciling to manual rounds
+pub fn spawn_reconciler(interval: Duration) {
+    if interval.is_zero() {
+        info!("reconciler disabled");
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = reconcile_all().await {
+                warn!(?err, "reconcile round failed");
+            }
+        }
+    });
+}
+
+/// Compare every desired state with the reported instances and deploy where they differ
+pub async fn reconcile_all() -> Result<Vec<ReconcileStatus>> {
+    // a manual round must not start a second deployment next to the scheduled one
+    static ROUND: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+    let _round = ROUND.lock().await;
+
+    let desired = {
+        let conn = &mut repositry::db_conn().await?;
+        repositry::desired_state::list(conn).await?
+    };
+
+    let mut results = HashMap::with_capacity(desired.len());
+    for desired in desired {
+        let status = match reconcile_app(&desired).await {
+            Ok(status) => status,
+            Err(err) => {
+                warn!(?err, app = %desired.app_id, "reconcile failed");
+                ReconcileStatus::new(&desired, ReconcileState::Error, Some(format!("{:#}", err)))
+            }
+        };
+        results.insert(desired.app_id, status);
+    }
+
+    let mut statuses = statuses().lock().unwrap();
+    *statuses = results;
+    Ok(statuses.values().cloned().collect())
+}
+
+impl ReconcileStatus {
+    fn new(desired: &DesiredState, state: ReconcileState, error: Option<String>) -> Self {
+        Self {
+            app_id: desired.app_id,
+            hash: desired.hash.clone(),
+            group_id: desired.group_id,
+            state,
+            missing: Vec::new(),
+            stale: Vec::new(),
+            failed: Vec::new(),
+            extra: Vec::new(),
+            unreachable: Vec::new(),
+            deployment: None,
+            error,
+            checked_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    /// Sort the reachable hosts of the group by their instance of the app and find the hosts
+    /// outside the group that still have it
+    fn classify(&mut self, group: &[HostId], instances: &[AppInstance]) {
+        let by_host: HashMap<_, _> = instances.iter().map(|i| (i.host_id, i)).collect();
+        for &host_id in group {
+            if self.unreachable.contains(&host_id) {
+                continue;
+            }
+            match by_host.get(&host_id) {
+                None => self.missing.push(host_id),
+                Some(i) if i.state == InstanceState::Missing => self.missing.push(host_id),
+                Some(i) if i.hash != self.hash || i.state != InstanceState::Installed => self.stale.push(host_id),
+                Some(_) => {}
+            }
+        }
+        let members: HashSet<_> = group.iter().collect();
+        self.extra = instances
+            .iter()
+            .filter(|i| !members.contains(&i.host_id) && i.state != InstanceState::Missing)
+            .map(|i| i.host_id)
+            .collect();
+    }
+
+    /// Take the hosts where the version failed out of the ones to deploy to
+    fn set_failed(&mut self, failed: Vec<HostId>) {
+        self.missing.retain(|h| !failed.contains(h));
+        self.stale.retain(|h| !failed.contains(h));
+        self.failed = failed;
+    }
+}
+
+/// Refresh the instances of the running hosts at once, returning the hosts that could not be asked
+async fn sync_hosts(hosts: &[Host]) -> Vec<HostId> {
+    let timeout = get_settings().monitor.timeout_secs;
+    let syncs = hosts.iter().map(|host| async move {
+        if host.state != HostState::Running {
+            return Some(host.id);
+        }
+        match tokio::time::timeout(Duration::from_secs(timeout), sync_host_instances(host)).await {
+            Ok(Ok(_)) => None,
+            Ok(Err(err)) => {
+                warn!(?err, host = %host.id, "cannot sync instances");
+                Some(host.id)
+            }
+            Err(_) => {
+                warn!(host = %host.id, "no instances within {}s", timeout);
+                Some(host.id)
+            }
+        }
+    });
+    join_all(syncs).await.into_iter().flatten().collect()
+}
+
+async fn reconcile_app(desired: &DesiredState) -> Result<ReconcileStatus> {
+    // not held while the envoys are asked
+    let hosts = {
+        let conn = &mut repositry::db_conn().await?;
+        let group = repositry::host_group::find(desired.group_id, conn)
+            .await?
+            .context("host group not found")?;
+        let mut hosts = Vec::with_capacity(group.hosts.len());
+        for &host_id in &group.hosts {
+            hosts.push(repositry::host::get(host_id, conn).await?.context("host not found")?);
+        }
+        hosts
+    };
+    let mut status = ReconcileStatus::new(desired, ReconcileState::Converged, None);
+    status.unreachable = sync_hosts(&hosts).await;
+
+    let latest = {
+        let conn = &mut repositry::db_conn().await?;
+        let instances = repositry::app_instance::list_by_app(desired.app_id, conn).await?;
+        let group: Vec<_> = hosts.iter().map(|h| h.id).collect();
+        status.classify(&group, &instances);
+
+        // deploying a version that failed again would only fail again, every round
+        let candidates: Vec<_> = status.missing.iter().chain(&status.stale).copied().collect();
+        let failed = repositry::deployment::failed_hosts(desired.app_id, &desired.hash, &candidates, conn).await?;
+        status.set_failed(failed);
+        repositry::deployment::latest(desired.app_id, conn).await?
+    };
+
+    let targets: Vec<_> = status.missing.iter().chain(&status.stale).copied().collect();
+    if targets.is_empty() {
+        if !status.failed.is_empty() {
+            status.state = ReconcileState::Error;
+            status.error = Some(format!("the deploy of {} failed on hosts {:?}", desired.hash, status.failed));
+        }
+        return Ok(status);
+    }
+    status.state = ReconcileState::Converging;
+
+    if let Some(latest) = latest.filter(|d| matches!(d.state, DeploymentState::Running | DeploymentState::WaitingApproval)) {
+        // let the running deployment finish, the next round looks again
+        status.deployment = Some(latest.id);
+        return Ok(status);
+    }
+
+    info!(app = %desired.app_id, hash = %desired.hash, hosts = ?targets, "converging");
+    let deployment = deploy::deploy(DeployParams {
+        app_id: desired.app_id,
+        hash: desired.hash.clone(),
+        hosts: targets,
+        strategy: DeployStrategy::AllAtOnce,
+        triggered_by: Some(TRIGGERED_BY.to_string()),
+    })
+    .await?;
+    status.deployment = Some(deployment.id);
+    Ok(status)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_classify() {
+        let host = HostId::from;
+        let instance = |host_id: i64, hash: &str, state| AppInstance {
+            app_id: AppId::from(1),
+            host_id: host(host_id),
+            hash: hash.to_string(),
+            install_dir: None,
+            state,
+            reported_hash: None,
+            reported_at: None,
+        };
+        let desired = DesiredState {
+            app_id: AppId::from(1),
+            hash: "v2".to_string(),
+            group_id: HostGroupId::from(1),
+        };
+        let instances = [
+            instance(1, "v2", InstanceState::Installed),
+            instance(2, "v1", InstanceState::Installed),
+            instance(3, "v2", InstanceState::Drifted),
+            instance(4, "v2", InstanceState::Missing),
+            instance(6, "v1", InstanceState::Installed),
+            instance(7, "v1", InstanceState::Installed),
+            instance(8, "v1", InstanceState::Missing),
+        ];
+        let mut status = ReconcileStatus::new(&desired, ReconcileState::Converged, None);
+        status.unreachable = vec![host(7)];
+        status.classify(&[1, 2, 3, 4, 5, 7].map(host), &instances);
+        assert_eq!(status.missing, [host(4), host(5)]);
+        assert_eq!(status.stale, [host(2), host(3)]);
+        // a host outside the group without the app is no extra
+        assert_eq!(status.extra, [host(6)]);
+
+        status.set_failed(vec![host(3), host(5)]);
+        assert_eq!(status.missing, [host(4)]);
+        assert_eq!(status.stale, [host(2)]);
+        assert_eq!(status.failed, [host(3), host(5)]);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Context, Result};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::repositry;
+
+use super::{
+    exec::{ExecParams, ExecResult},
+    group::HostGroupId,
+    HostId,
+};
+
+const DEFAULT_CONCURRENCY: usize = 16;
+/// The whole batch answers only once every host is done, a hung command must not hold it forever
+const DEFAULT_TIMEOUT_SECS: u32 = 300;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchExecParams {
+    #[serde(default)]
+    pub hosts: Vec<HostId>,
+    /// run on the members of the group as well
+    #[serde(default)]
+    pub group: Option<HostGroupId>,
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// `timeoutSecs` defaults to 5 minutes here, 0 lets the commands run without a limit
+    #[serde(flatten)]
+    pub exec: ExecParams,
+}
+
+/// The hosts of a batch exec grouped by what they answered
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchExecSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// the largest group first
+    pub groups: Vec<OutputGroup>,
+}
+
+/// Hosts that printed exactly the same and ended the same way
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OutputGroup {
+    pub success: bool,
+    /// `None` when the command could not be run at all
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+    /// why the command could not be run
+    pub error: Option<String>,
+    pub hosts: Vec<HostId>,
+}
+
+/// Run the command on `hosts` and the members of `group`, at most `concurrency` at a time, and wait for all of them
+pub async fn batch_exec(params: BatchExecParams) -> Result<BatchExecSummary> {
+    let BatchExecParams {
+        mut hosts,
+        group,
+        concurrency,
+        mut exec,
+    } = params;
+    let concurrency = concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+    ensure!(concurrency > 0, "concurrency must be positive");
+    exec.validate()?;
+    exec.timeout_secs.get_or_insert(DEFAULT_TIMEOUT_SECS);
+
+    // the connection goes back to the pool before the commands run
+    let targets = {
+        let conn = &mut repositry::db_conn().await?;
+        if let Some(group) = group {
+            let group = repositry::host_group::find(group, conn)
+                .await?
+                .with_context(|| format!("host group {} not found", group))?;
+            hosts.extend(group.hosts);
+        }
+        hosts.sort();
+        hosts.dedup();
+        ensure!(!hosts.is_empty(), "no host to run on");
+
+        let mut targets = Vec::with_capacity(hosts.len());
+        for id in hosts {
+            let host = repositry::host::get(id, conn)
+                .await?
+                .with_context(|| format!("host {} not found", id))?;
+            targets.push(host);
+        }
+        targets
+    };
+    info!(hosts = targets.len(), concurrency, "batch exec");
+
+    let results: Vec<_> = stream::iter(targets)
+        .map(|host| {
+            let exec = exec.clone();
+            async move { (host.id, host.exec_collect(exec).await) }
+        })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    Ok(summarize(results))
+}
+
+fn summarize(results: Vec<(HostId, Result<ExecResult>)>) -> BatchExecSummary {
+    let mut groups: BTreeMap<OutputGroup, Vec<HostId>> = BTreeMap::new();
+    for (host, result) in results {
+        let key = match result {
+            Ok(ExecResult { exit, stdout, stderr }) => OutputGroup {
+                success: exit.code == 0 && !exit.timed_out,
+                exit_code: Some(exit.code),
+                timed_out: exit.timed_out,
+                stdout,
+                stderr,
+                error: None,
+                hosts: Vec::new(),
+            },
+            Err(err) => OutputGroup {
+                success: false,
+                exit_code: None,
+                timed_out: false,
+                stdout: String::new(),
+                stderr: String::new(),
+                error: Some(format!("{:#}", err)),
+                hosts: Vec::new(),
+            },
+        };
+        groups.entry(key).or_default().push(host);
+    }
+
+    let mut groups: Vec<_> = groups
+        .into_iter()
+        .map(|(group, mut hosts)| {
+            hosts.sort();
+            OutputGroup { hosts, ..group }
+        })
+        .collect();
+    groups.sort_by_key(|g| std::cmp::Reverse(g.hosts.len()));
+
+    let count = |success: bool| groups.iter().filter(|g| g.success == success).map(|g| g.hosts.len()).sum();
+    BatchExecSummary {
+        succeeded: count(true),
+        failed: count(false),
+        groups,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::host::exec::ExecExit;
+
+    fn ok(code: i32, stdout: &str) -> Result<ExecResult> {
+        Ok(ExecResult {
+            exit: ExecExit { code, timed_out: false },
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        })
+    }
+
+    #[test]
+    fn t_summarize() {
+        let results = vec![
+            (HostId::from(1), ok(0, "a")),
+            (HostId::from(2), ok(1, "a")),
+            (HostId::from(3), ok(0, "a")),
+            (HostId::from(4), Err(anyhow::anyhow!("refused"))),
+        ];
+        let summary = summarize(results);
+        assert_eq!((summary.succeeded, summary.failed), (2, 2));
+        assert_eq!(summary.groups.len(), 3);
+        assert_eq!(summary.groups[0].hosts, [HostId::from(1), HostId::from(3)]);
+        assert!(summary.groups[0].success);
+    }
+}
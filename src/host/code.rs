@@ -0,0 +1,16 @@
+utils::code! {
+    mod = "host";
+    index = 1;
+    err_trait = utils::macros::code::Code;
+
+    ---
+
+    CreateHost {
+        unreachable = "host unreachable, check the ip and the network",
+        refused = "connection refused, check the ssh port and that sshd is running",
+        timeout = "connection timed out, check the firewall in front of the ssh port",
+        auth_rejected = "ssh key rejected, check the user and the key",
+        host_key_mismatch = "host key changed since the first contact, check the host before removing its known_hosts entry",
+        command_failed = "a command failed on the host, check its output",
+    }
+}
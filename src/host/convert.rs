@@ -1,6 +1,12 @@
-use crate::repositry::host::HostPo;
+use std::borrow::Cow;
 
-use super::Host;
+use crate::repositry::{
+    host::HostPo,
+    host_event::HostEventPo,
+    host_group::{HostGroupMemberPo, HostGroupPo},
+};
+
+use super::{event::HostEvent, group::HostGroup, ssh::SshParams, EnvoyInfo, Host};
 
 impl<'a> From<&'a Host> for HostPo<'a> {
     fn from(value: &'a Host) -> Self {
@@ -12,7 +18,7 @@ impl TryFrom<HostPo<'static>> for Host {
     type Error = anyhow::Error;
 
     fn try_from(value: HostPo<'static>) -> Result<Self, Self::Error> {
-        Ok(from_po(value))
+        from_po(value)
     }
 }
 
@@ -21,14 +27,90 @@ pub fn to_po(host: &Host) -> HostPo {
         id: host.id,
         name: (&host.name).into(),
         ip: host.ip.to_string().into(),
+        ssh_port: host.ssh.port.into(),
+        ssh_user: (&host.ssh.user).into(),
+        ssh_key: host.ssh.key.as_deref().map(Into::into),
+        envoy_version: host.envoy.as_ref().map(|e| e.version.as_str().into()),
+        envoy_commit: host.envoy.as_ref().map(|e| e.commit.as_str().into()),
+        envoy_protocol: host.envoy.as_ref().map(|e| e.protocol as i32),
+        state: host.state,
+        last_seen_at: host.last_seen_at,
+        failures: host.failures as i32,
+        facts: host.facts.as_ref().and_then(|f| serde_json::to_string(f).ok()).map(Into::into),
     }
 }
 
-pub fn from_po(po: HostPo) -> Host {
-    Host {
+pub fn from_po(po: HostPo) -> anyhow::Result<Host> {
+    let envoy = match (po.envoy_version, po.envoy_commit, po.envoy_protocol) {
+        (Some(version), Some(commit), Some(protocol)) => Some(EnvoyInfo {
+            version: version.into_owned(),
+            commit: commit.into_owned(),
+            protocol: protocol.try_into()?,
+        }),
+        _ => None,
+    };
+    Ok(Host {
         id: po.id,
         name: po.name.into_owned(),
-        ip: po.ip.parse().unwrap(),
-        state: super::HostState::Disconnected,
+        ip: po.ip.parse()?,
+        state: po.state,
+        last_seen_at: po.last_seen_at,
+        failures: po.failures.try_into()?,
+        ssh: SshParams {
+            port: po.ssh_port.try_into()?,
+            user: po.ssh_user.into_owned(),
+            key: po.ssh_key.map(Cow::into_owned),
+        },
+        facts: po.facts.map(|f| serde_json::from_str(&f)).transpose()?,
+        outdated: envoy.as_ref().is_some_and(EnvoyInfo::outdated),
+        envoy,
+    })
+}
+
+impl<'a> From<&'a HostGroup> for HostGroupPo<'a> {
+    fn from(value: &'a HostGroup) -> Self {
+        HostGroupPo {
+            id: value.id,
+            name: (&value.name).into(),
+        }
+    }
+}
+
+impl TryFrom<(HostGroupPo<'static>, Vec<HostGroupMemberPo>)> for HostGroup {
+    type Error = anyhow::Error;
+
+    fn try_from(value: (HostGroupPo<'static>, Vec<HostGroupMemberPo>)) -> Result<Self, Self::Error> {
+        let (group, members) = value;
+        Ok(HostGroup {
+            id: group.id,
+            name: group.name.into_owned(),
+            hosts: members.into_iter().map(|m| m.host_id).collect(),
+        })
+    }
+}
+
+impl<'a> From<&'a HostEvent> for HostEventPo<'a> {
+    fn from(value: &'a HostEvent) -> Self {
+        HostEventPo {
+            id: value.id,
+            host_id: value.host_id,
+            from_state: value.from,
+            state: value.state,
+            reason: (&value.reason).into(),
+            at: value.at,
+        }
+    }
+}
+
+impl From<HostEventPo<'static>> for HostEvent {
+    fn from(value: HostEventPo<'static>) -> Self {
+        HostEvent {
+            id: value.id,
+            host_id: value.host_id,
+            from: value.from_state,
+            state: value.state,
+            reason: value.reason.into_owned(),
+            at: value.at,
+        }
     }
 }
@@ -0,0 +1,92 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use utils::id_new_type;
+
+use super::{HostId, HostState};
+
+id_new_type!(HostEventId);
+
+/// A change of the state of a host
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostEvent {
+    pub id: HostEventId,
+    pub host_id: HostId,
+    /// `None` when the host was created
+    pub from: Option<HostState>,
+    pub state: HostState,
+    /// the health check error, or what the operator did
+    pub reason: String,
+    pub at: NaiveDateTime,
+}
+
+impl HostEvent {
+    pub fn new(host_id: HostId, from: Option<HostState>, state: HostState, reason: String) -> Self {
+        Self {
+            id: HostEventId::next_id(),
+            host_id,
+            from,
+            state,
+            reason,
+            at: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+
+/// Percentage of `start..end` the host was running.
+///
+/// `before` is the last event before `start`, it tells the state the window starts with. Time
+/// before the first known state is left out, `None` if no state is known in the window at all.
+pub fn uptime(before: Option<&HostEvent>, events: &[HostEvent], start: NaiveDateTime, end: NaiveDateTime) -> Option<f64> {
+    let mut state = before.map(|e| (e.state, start));
+    let mut known = chrono::Duration::zero();
+    let mut running = chrono::Duration::zero();
+
+    let mut account = |state: Option<(HostState, NaiveDateTime)>, until: NaiveDateTime| {
+        if let Some((state, since)) = state {
+            known += until - since;
+            if state == HostState::Running {
+                running += until - since;
+            }
+        }
+    };
+    for event in events.iter().filter(|e| e.at >= start && e.at < end) {
+        account(state, event.at);
+        state = Some((event.state, event.at));
+    }
+    account(state, end);
+
+    if known.is_zero() {
+        return None;
+    }
+    Some(running.num_milliseconds() as f64 * 100.0 / known.num_milliseconds() as f64)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 11, 23).unwrap().and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    fn event(hour: u32, state: HostState) -> HostEvent {
+        HostEvent {
+            at: at(hour),
+            ..HostEvent::new(HostId::from(1), None, state, String::new())
+        }
+    }
+
+    #[test]
+    fn t_uptime() {
+        let before = event(0, HostState::Running);
+        let events = [event(2, HostState::Disconnected), event(3, HostState::Running)];
+        assert_eq!(uptime(Some(&before), &events, at(1), at(5)), Some(75.0));
+
+        // unknown until the first event
+        assert_eq!(uptime(None, &events, at(1), at(5)), Some(200.0 / 3.0));
+        assert_eq!(uptime(None, &[], at(1), at(5)), None);
+    }
+}
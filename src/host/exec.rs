@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{ensure, Context, Result};
+use futures::{
+    stream::{AbortHandle, Abortable},
+    Stream, StreamExt, TryStreamExt,
+};
+use serde::{Deserialize, Serialize};
+use utils::id_new_type;
+use volo_gen::av1::operator::{exec_output::Output, ExecReq};
+
+use super::Host;
+
+id_new_type!(ExecId);
+
+/// Output kept of each stream when collecting, the rest is dropped
+const MAX_OUTPUT: usize = 64 * 1024;
+
+/// A command to run on a host through its envoy
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecParams {
+    /// the program to run, leave it out to run `script` with bash
+    #[serde(default)]
+    pub command: Option<String>,
+    #[serde(default)]
+    pub script: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// no limit when absent
+    #[serde(default)]
+    pub timeout_secs: Option<u32>,
+}
+
+impl ExecParams {
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.command.is_some() != self.script.is_some(),
+            "exactly one of command and script is required"
+        );
+        Ok(())
+    }
+
+    fn into_req(self) -> Result<ExecReq> {
+        self.validate()?;
+        Ok(ExecReq {
+            command: self.command.unwrap_or_default().into(),
+            args: self.args.into_iter().map(Into::into).collect(),
+            env: self.env.into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+            cwd: self.cwd.unwrap_or_default().into(),
+            timeout_secs: self.timeout_secs.unwrap_or_default(),
+            script: self.script.unwrap_or_default().into(),
+        })
+    }
+}
+
+/// Output of a remote command as it arrives
+#[derive(Debug, Clone)]
+pub enum ExecEvent {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exit(ExecExit),
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecExit {
+    /// -1 when killed by a signal
+    pub code: i32,
+    pub timed_out: bool,
+}
+
+/// Everything a command printed, see [`Host::exec_collect`]
+#[derive(Debug, Clone)]
+pub struct ExecResult {
+    pub exit: ExecExit,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Host {
+    /// Run a command on the host, the command is killed when the stream is dropped
+    pub async fn exec(&self, params: ExecParams) -> Result<impl Stream<Item = Result<ExecEvent>>> {
+        let req = params.into_req()?;
+        let resp = self.client().exec(req).await.context("exec")?;
+        Ok(resp
+            .into_inner()
+            .map_err(|status| anyhow::Error::from(status).context("exec"))
+            .try_filter_map(|output| async move {
+                Ok(output.output.map(|output| match output {
+                    Output::Stdout(data) => ExecEvent::Stdout(data.into()),
+                    Output::Stderr(data) => ExecEvent::Stderr(data.into()),
+                    Output::Exit(exit) => ExecEvent::Exit(ExecExit {
+                        code: exit.code,
+                        timed_out: exit.timed_out,
+                    }),
+                }))
+            }))
+    }
+
+    /// Run a command and wait for it, keeping the first [`MAX_OUTPUT`] bytes of stdout and stderr
+    pub async fn exec_collect(&self, params: ExecParams) -> Result<ExecResult> {
+        let mut events = std::pin::pin!(self.exec(params).await?);
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        while let Some(event) = events.try_next().await? {
+            match event {
+                ExecEvent::Stdout(data) => append_capped(&mut stdout, &data),
+                ExecEvent::Stderr(data) => append_capped(&mut stderr, &data),
+                ExecEvent::Exit(exit) => {
+                    return Ok(ExecResult {
+                        exit,
+                        stdout: String::from_utf8_lossy(&stdout).into_owned(),
+                        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+                    })
+                }
+            }
+        }
+        anyhow::bail!("the envoy ended the exec without an exit status")
+    }
+}
+
+fn append_capped(buf: &mut Vec<u8>, data: &[u8]) {
+    let room = MAX_OUTPUT.saturating_sub(buf.len());
+    buf.extend_from_slice(&data[..data.len().min(room)]);
+}
+
+fn running() -> &'static Mutex<HashMap<ExecId, AbortHandle>> {
+    static RUNNING: OnceLock<Mutex<HashMap<ExecId, AbortHandle>>> = OnceLock::new();
+    RUNNING.get_or_init(Default::default)
+}
+
+/// Forgets the exec once its stream is gone, however it ended
+struct Running(ExecId);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        running().lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Make `events` cancellable with [`cancel_exec`] under a new id
+pub fn track<S: Stream>(events: S) -> (ExecId, impl Stream<Item = S::Item>) {
+    let id = ExecId::next_id();
+    let (handle, registration) = AbortHandle::new_pair();
+    running().lock().unwrap().insert(id, handle);
+
+    let guard = Running(id);
+    let events = Abortable::new(events, registration).map(move |event| {
+        let _ = &guard;
+        event
+    });
+    (id, events)
+}
+
+/// Stop a running exec, its stream ends and the envoy kills the command. False if it already ended
+pub fn cancel_exec(id: ExecId) -> bool {
+    match running().lock().unwrap().remove(&id) {
+        Some(handle) => {
+            handle.abort();
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use volo_gen::av1::operator::{GetFactsReq, GetFactsResp};
+
+use super::Host;
+
+/// What the host is made of, as reported by its envoy
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostFacts {
+    pub cpu_model: String,
+    /// logical cores
+    pub cpu_cores: u32,
+    /// avx2 and avx512f give AV1 encoders their fast paths
+    pub cpu_flags: Vec<String>,
+    pub mem_total_bytes: u64,
+    pub mem_available_bytes: u64,
+    pub disks: Vec<DiskFacts>,
+    pub os_release: String,
+    pub kernel: String,
+    pub arch: String,
+    pub collected_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiskFacts {
+    pub mount_point: String,
+    pub filesystem: String,
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+}
+
+impl From<GetFactsResp> for HostFacts {
+    fn from(resp: GetFactsResp) -> Self {
+        Self {
+            cpu_model: resp.cpu_model.to_string(),
+            cpu_cores: resp.cpu_cores,
+            cpu_flags: resp.cpu_flags.into_iter().map(|f| f.to_string()).collect(),
+            mem_total_bytes: resp.mem_total_bytes,
+            mem_available_bytes: resp.mem_available_bytes,
+            disks: resp
+                .disks
+                .into_iter()
+                .map(|d| DiskFacts {
+                    mount_point: d.mount_point.to_string(),
+                    filesystem: d.filesystem.to_string(),
+                    total_bytes: d.total_bytes,
+                    available_bytes: d.available_bytes,
+                })
+                .collect(),
+            os_release: resp.os_release.to_string(),
+            kernel: resp.kernel.to_string(),
+            arch: resp.arch.to_string(),
+            collected_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+
+impl Host {
+    /// Ask the envoy for the facts of the host and keep them on the host
+    pub async fn refresh_facts(&mut self) -> Result<&HostFacts> {
+        let resp = self.client().get_facts(GetFactsReq {}).await.context("get facts")?;
+        Ok(self.facts.insert(resp.into_inner().into()))
+    }
+}
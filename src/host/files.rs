@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use actix_web::web::Bytes;
+use anyhow::{bail, ensure, Context, Result};
+use futures::{stream, Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tracing::{info, warn};
+use volo_gen::av1::operator::{GetFileReq, PutFileHeader, PutFileReq, UploadOffsetReq};
+use volo_grpc::{Code, Status};
+
+use super::Host;
+
+const CHUNK_SIZE: usize = 256 * 1024;
+/// Attempts of an upload, each one goes on from what the envoy confirmed
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+/// The envoy predates the rpc, callers may fall back to the old way
+pub fn unimplemented(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Status>().is_some_and(|s| s.code() == Code::Unimplemented)
+}
+
+pub async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await.with_context(|| format!("open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl Host {
+    /// Upload `local` to `remote` through the envoy. The file shows up there only once complete,
+    /// with `mode` and, when given, `owner` (`user` or `user:group`).
+    pub async fn put_file(&self, local: &Path, remote: &str, mode: u32, owner: Option<&str>) -> Result<()> {
+        let sha256 = sha256_file(local).await?;
+        self.put_file_with_sha256(local, &sha256, remote, mode, owner).await
+    }
+
+    /// [`Host::put_file`] for a file whose sha256 is known already, like an artifact
+    pub async fn put_file_with_sha256(&self, local: &Path, sha256: &str, remote: &str, mode: u32, owner: Option<&str>) -> Result<()> {
+        let size = tokio::fs::metadata(local).await.with_context(|| format!("stat {:?}", local))?.len();
+        let mut attempt = 1;
+        loop {
+            match self.put_file_from_offset(local, remote, mode, owner, size, sha256).await {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < UPLOAD_ATTEMPTS && !unimplemented(&err) => {
+                    warn!(?err, host = %self.id, remote, attempt, "upload interrupted, resuming");
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn put_file_from_offset(
+        &self,
+        local: &Path,
+        remote: &str,
+        mode: u32,
+        owner: Option<&str>,
+        size: u64,
+        sha256: &str,
+    ) -> Result<()> {
+        let client = self.client();
+        let req = UploadOffsetReq {
+            path: remote.to_string().into(),
+            sha256: sha256.to_string().into(),
+        };
+        let offset = client
+            .upload_offset(req)
+            .await
+            .map_err(anyhow::Error::from)
+            .context("get upload offset")?
+            .into_inner()
+            .offset
+            .min(size);
+        info!(host = %self.id, ?local, remote, size, offset, "uploading file");
+
+        let mut file = File::open(local).await.with_context(|| format!("open {:?}", local))?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let header = PutFileHeader {
+            path: remote.to_string().into(),
+            mode,
+            owner: owner.unwrap_or_default().to_string().into(),
+            size,
+            sha256: sha256.to_string().into(),
+            offset,
+        };
+        // a read error ends the upload short, the envoy keeps what it got for the next attempt
+        let chunks = stream::unfold((file, Some(header)), |(mut file, header)| async move {
+            let mut buf = vec![0; CHUNK_SIZE];
+            let n = match file.read(&mut buf).await {
+                Ok(n) => n,
+                Err(err) => {
+                    warn!(?err, "read file to upload");
+                    return None;
+                }
+            };
+            if n == 0 && header.is_none() {
+                return None;
+            }
+            buf.truncate(n);
+            Some((PutFileReq { header, data: buf.into() }, (file, None)))
+        });
+        client.put_file(chunks).await.map_err(anyhow::Error::from).context("put file")?;
+        Ok(())
+    }
+
+    /// Download `remote` through the envoy, the stream fails at the end if the sha256 does not match
+    pub async fn get_file(&self, remote: &str) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let req = GetFileReq {
+            path: remote.to_string().into(),
+        };
+        let chunks = self
+            .client()
+            .get_file(req)
+            .await
+            .map_err(anyhow::Error::from)
+            .context("get file")?
+            .into_inner();
+
+        let state = (Box::pin(chunks), Some(Sha256::new()));
+        Ok(stream::try_unfold(state, |(mut chunks, hasher)| async move {
+            let Some(mut hasher) = hasher else { return Ok(None) };
+            let Some(chunk) = chunks.next().await else {
+                bail!("download ended before the checksum");
+            };
+            let chunk = chunk.map_err(anyhow::Error::from).context("get file")?;
+            hasher.update(&chunk.data);
+            if chunk.sha256.is_empty() {
+                return Ok(Some((chunk.data, (chunks, Some(hasher)))));
+            }
+            let actual = format!("{:x}", hasher.finalize());
+            ensure!(actual == *chunk.sha256, "sha256 mismatch. expect {}, got {}", chunk.sha256, actual);
+            Ok(Some((chunk.data, (chunks, None))))
+        }))
+    }
+}
@@ -0,0 +1,25 @@
+use serde::Serialize;
+use utils::id_new_type;
+
+use super::HostId;
+
+id_new_type!(HostGroupId);
+
+/// A named set of hosts, the unit apps are declared to run on
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostGroup {
+    pub id: HostGroupId,
+    pub name: String,
+    pub hosts: Vec<HostId>,
+}
+
+impl HostGroup {
+    pub fn new(name: String, hosts: Vec<HostId>) -> Self {
+        Self {
+            id: HostGroupId::next_id(),
+            name,
+            hosts,
+        }
+    }
+}
@@ -1,86 +1,514 @@
-use std::net::IpAddr;
-
-use actix_web::web::{self, Json, Query};
-use tracing::debug;
+use actix_web::{
+    web::{self, Json, Payload, Query},
+    HttpResponse,
+};
+use chrono::NaiveDateTime;
+use futures::{stream, Stream, StreamExt};
+use tokio::{fs, io::AsyncWriteExt};
+use tracing::{debug, warn};
+use utils::id_new_type;
 
 use crate::{
-    http::{ApiResponse, ApiResult, Pagination},
+    application::instance::{sync_host_instances, AppInstance},
+    http::{sse, ApiError, ApiResponse, ApiResult, Pagination, SseEvent},
     repositry::{self, host, PageList},
+    settings::get_settings,
 };
 
-use super::{ssh::HostBuilder, Host, HostId};
+use super::{
+    batch::{self, BatchExecParams, BatchExecSummary},
+    code::CREATE_HOST,
+    create_host as create_host_inner, delete_host as delete_host_inner,
+    event::{self, HostEvent},
+    exec::{self, ExecEvent, ExecId, ExecParams},
+    facts::HostFacts,
+    group::{HostGroup, HostGroupId},
+    journal::JournalParams,
+    service::{ServiceAction, ServiceStatus},
+    ssh,
+    transport::SshError,
+    upgrade::{self, EnvoyUpgrade, EnvoyUpgradeId},
+    CreateHostParams, Host, HostId,
+};
 
 pub fn config(cfg: &mut actix_web::web::ServiceConfig) {
-    cfg.service(
-        web::scope("/api/operator")
-            .route("ping_host", web::get().to(ping_host))
-            .route("hosts", web::post().to(host_list))
-            .route("create_host", web::post().to(create_host)),
-    );
+    cfg.route("ping_host", web::get().to(ping_host))
+        .route("host_events", web::get().to(host_events))
+        .route("refresh_facts", web::post().to(refresh_facts))
+        .route("host_metrics", web::get().to(host_metrics))
+        .route("exec", web::post().to(exec))
+        .route("cancel_exec", web::post().to(cancel_exec))
+        .route("batch_exec", web::post().to(batch_exec))
+        .route("put_file", web::post().to(put_file))
+        .route("get_file", web::get().to(get_file))
+        .route("service_status", web::get().to(service_status))
+        .route("service_control", web::post().to(service_control))
+        .route("host_journal", web::get().to(host_journal))
+        .route("hosts", web::post().to(host_list))
+        .route("create_host", web::post().to(create_host))
+        .route("delete_host", web::post().to(delete_host))
+        .route("reinstall_envoy", web::post().to(reinstall_envoy))
+        .route("upgrade_envoys", web::post().to(upgrade_envoys))
+        .route("envoy_upgrade", web::get().to(envoy_upgrade))
+        .route("envoy_upgrades", web::get().to(envoy_upgrades))
+        .route("host_instances", web::get().to(host_instances))
+        .route("sync_instances", web::post().to(sync_instances))
+        .route("create_host_group", web::post().to(create_host_group))
+        .route("host_groups", web::post().to(host_group_list))
+        .route("set_host_group_members", web::post().to(set_host_group_members));
+}
+
+pub async fn create_host(params: Json<CreateHostParams>) -> ApiResult<HostId> {
+    let host = create_host_inner(params.into_inner()).await.map_err(create_host_err)?;
+    ApiResponse::ok(host.id)
+}
+
+/// Give ssh failures their own code so the operator knows what to fix
+fn create_host_err(err: anyhow::Error) -> ApiError {
+    let Some(ssh) = err.downcast_ref::<SshError>() else {
+        return err.into();
+    };
+    let code = match ssh {
+        SshError::Unreachable(_) => CREATE_HOST.unreachable,
+        SshError::Refused => CREATE_HOST.refused,
+        SshError::Timeout => CREATE_HOST.timeout,
+        SshError::AuthRejected(_) => CREATE_HOST.auth_rejected,
+        SshError::HostKeyMismatch => CREATE_HOST.host_key_mismatch,
+        SshError::Command { .. } => CREATE_HOST.command_failed,
+        SshError::Other(_) => return err.into(),
+    };
+    ApiError::with_code(code, err)
 }
 
 #[derive(serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct CreateHostParams {
-    pub name: String,
-    pub ip: IpAddr,
-    #[serde(default)]
-    pub port: Option<u16>,
-    #[serde(default)]
-    pub user: Option<String>,
+pub struct HostIdParams {
+    id: HostId,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteHostParams {
+    id: HostId,
     #[serde(default)]
-    pub key: Option<String>,
+    force: bool,
 }
 
-pub async fn create_host(params: Json<CreateHostParams>) -> ApiResult<HostId> {
-    let CreateHostParams { name, ip, port, user, key } = params.into_inner();
+pub async fn delete_host(params: Query<DeleteHostParams>) -> ApiResult<()> {
+    let DeleteHostParams { id, force } = params.into_inner();
+    delete_host_inner(id, force).await?;
+    ApiResponse::ok(())
+}
 
-    let mut builder = HostBuilder::new(name, ip);
-    if let Some(port) = port {
-        builder = builder.port(port);
+pub async fn ping_host(params: Query<HostIdParams>) -> ApiResult<()> {
+    let HostIdParams { id } = params.into_inner();
+    debug!(?id, "ping host");
+    let conn = &mut repositry::db_conn().await?;
+    let mut host = repositry::host::get(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host not found"))?;
+
+    let event = host.ping().await;
+    host::update_health(&host, conn).await?;
+    if let Some(mut event) = event {
+        event.reason = format!("manual check: {}", event.reason);
+        repositry::host_event::save(&event, conn).await?;
     }
-    if let Some(user) = user {
-        builder = builder.user(user.into());
+    ApiResponse::ok(())
+}
+
+/// Collect the facts of the host now instead of waiting for it to come up again
+pub async fn refresh_facts(params: Query<HostIdParams>) -> ApiResult<HostFacts> {
+    let HostIdParams { id } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let mut host = repositry::host::get(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host not found"))?;
+
+    let facts = host.refresh_facts().await?.clone();
+    host::update_health(&host, conn).await?;
+    ApiResponse::ok(facts)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostMetricsParams {
+    id: HostId,
+    /// time between samples, the envoy picks 1s when absent
+    #[serde(default)]
+    interval_ms: u32,
+}
+
+/// Server-Sent Events: one `data` event per sample as json, an `error` event if the envoy fails mid stream
+pub async fn host_metrics(params: Query<HostMetricsParams>) -> Result<HttpResponse, ApiError> {
+    let HostMetricsParams { id, interval_ms } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let host = repositry::host::get(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host not found"))?;
+
+    let samples = host.metrics(interval_ms).await?;
+    let events = samples.map(|sample| match sample {
+        Ok(sample) => SseEvent::data(serde_json::to_string(&sample).unwrap_or_default()),
+        Err(err) => SseEvent::named("error", format!("{:#}", err)),
+    });
+    Ok(sse(events))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostExecParams {
+    id: HostId,
+    #[serde(flatten)]
+    exec: ExecParams,
+}
+
+/// Server-Sent Events: `started` with the exec id, `stdout` and `stderr` chunks, then `exit` as json.
+/// The stream ends without `exit` when the exec is cancelled, and with `error` when the envoy fails.
+pub async fn exec(params: Json<HostExecParams>) -> Result<HttpResponse, ApiError> {
+    let HostExecParams { id, exec } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let host = repositry::host::get(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host not found"))?;
+
+    let output = host.exec(exec).await?;
+    let (exec_id, output) = exec::track(output);
+    debug!(host = %id, %exec_id, "exec");
+    let started = stream::once(async move { SseEvent::named("started", exec_id.to_string()) });
+    let events = output.map(|event| match event {
+        Ok(ExecEvent::Stdout(data)) => SseEvent::named("stdout", String::from_utf8_lossy(&data)),
+        Ok(ExecEvent::Stderr(data)) => SseEvent::named("stderr", String::from_utf8_lossy(&data)),
+        Ok(ExecEvent::Exit(exit)) => SseEvent::named("exit", serde_json::to_string(&exit).unwrap_or_default()),
+        Err(err) => SseEvent::named("error", format!("{:#}", err)),
+    });
+    Ok(sse(started.chain(events)))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecIdParams {
+    id: ExecId,
+}
+
+/// Kill a command started with `exec`, false when it already ended
+pub async fn cancel_exec(params: Query<ExecIdParams>) -> ApiResult<bool> {
+    let ExecIdParams { id } = params.into_inner();
+    ApiResponse::ok(exec::cancel_exec(id))
+}
+
+/// Run a command on many hosts and answer once every host is done
+pub async fn batch_exec(params: Json<BatchExecParams>) -> ApiResult<BatchExecSummary> {
+    let summary = batch::batch_exec(params.into_inner()).await?;
+    ApiResponse::ok(summary)
+}
+
+id_new_type!(UploadId);
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostFileParams {
+    id: HostId,
+    path: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PutFileParams {
+    id: HostId,
+    path: String,
+    /// octal permission bits like `640`, 644 when absent
+    mode: Option<String>,
+    /// `user` or `user:group`
+    owner: Option<String>,
+}
+
+/// Write the request body to `path` on the host, e.g. a config file
+pub async fn put_file(params: Query<PutFileParams>, mut body: Payload) -> ApiResult<()> {
+    let PutFileParams { id, path, mode, owner } = params.into_inner();
+    let mode = match mode {
+        Some(mode) => u32::from_str_radix(&mode, 8).map_err(|_| anyhow::anyhow!("mode must be octal: {}", mode))?,
+        None => 0o644,
+    };
+    // not held while the body is spooled and uploaded
+    let host = {
+        let conn = &mut repositry::db_conn().await?;
+        repositry::host::get(id, conn)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("host not found"))?
+    };
+
+    // spooled to disk so a large file is never held in memory, and can be resumed from there
+    let local = get_settings().data_dir.upload_dir().join(UploadId::next_id().to_string());
+    let uploaded = async {
+        let mut file = fs::File::create(&local).await?;
+        while let Some(chunk) = body.next().await {
+            file.write_all(&chunk.map_err(|err| anyhow::anyhow!("read body: {}", err))?).await?;
+        }
+        file.flush().await?;
+        host.put_file(&local, &path, mode, owner.as_deref()).await
     }
-    if let Some(key) = key {
-        builder = builder.key(key);
+    .await;
+    if let Err(err) = fs::remove_file(&local).await {
+        warn!(?err, ?local, "remove spooled upload");
     }
+    uploaded?;
+    ApiResponse::ok(())
+}
 
-    let mut host = builder.build().await?;
-    host.ping().await;
+/// Download `path` from the host
+pub async fn get_file(params: Query<HostFileParams>) -> Result<HttpResponse, ApiError> {
+    let HostFileParams { id, path } = params.into_inner();
+    // not held while the file is streamed
+    let host = {
+        let conn = &mut repositry::db_conn().await?;
+        repositry::host::get(id, conn)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("host not found"))?
+    };
 
+    let chunks = host.get_file(&path).await?;
+    let body = chunks.map(|chunk| chunk.map_err(|err| actix_web::error::ErrorInternalServerError(format!("{:#}", err))));
+    Ok(HttpResponse::Ok().content_type("application/octet-stream").streaming(body))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostServiceParams {
+    id: HostId,
+    unit: String,
+    /// only the status is read when absent
+    #[serde(default)]
+    action: Option<ServiceAction>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceStatusParams {
+    id: HostId,
+    unit: String,
+}
+
+pub async fn service_status(params: Query<ServiceStatusParams>) -> ApiResult<ServiceStatus> {
+    let ServiceStatusParams { id, unit } = params.into_inner();
+    // not held while the envoy runs systemctl
+    let host = {
+        let conn = &mut repositry::db_conn().await?;
+        repositry::host::get(id, conn)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("host not found"))?
+    };
+
+    ApiResponse::ok(host.service_control(&unit, None).await?)
+}
+
+/// Start, stop, restart, enable or disable a systemd unit on the host, answering its status afterwards
+pub async fn service_control(params: Json<HostServiceParams>) -> ApiResult<ServiceStatus> {
+    let HostServiceParams { id, unit, action } = params.into_inner();
+    // not held while the envoy runs systemctl
+    let host = {
+        let conn = &mut repositry::db_conn().await?;
+        repositry::host::get(id, conn)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("host not found"))?
+    };
+
+    ApiResponse::ok(host.service_control(&unit, action).await?)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostJournalParams {
+    id: HostId,
+    /// `av1-envoy` for the envoy itself
+    unit: String,
+    since: Option<String>,
+    /// the last 100 lines when neither `since` nor `lines` is given
+    lines: Option<u32>,
+    #[serde(default)]
+    follow: bool,
+}
+
+/// Server-Sent Events: one `data` event per journal line, an `error` event if journalctl fails,
+/// `ping` while a followed journal is quiet
+pub async fn host_journal(params: Query<HostJournalParams>) -> Result<HttpResponse, ApiError> {
+    let HostJournalParams {
+        id,
+        unit,
+        since,
+        lines,
+        follow,
+    } = params.into_inner();
     let conn = &mut repositry::db_conn().await?;
-    repositry::host::save(&host, conn).await?;
+    let host = repositry::host::get(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host not found"))?;
 
-    ApiResponse::ok(host.id)
+    let params = JournalParams {
+        unit,
+        since,
+        lines,
+        follow,
+    };
+    Ok(journal_sse(host.journal(params).await?))
+}
+
+/// A `ping` event goes out when the journal is quiet for this long, so a stream whose client
+/// went away is noticed and journalctl on the host stopped
+const JOURNAL_PING: std::time::Duration = std::time::Duration::from_secs(15);
+
+pub fn journal_sse(lines: impl Stream<Item = anyhow::Result<String>> + 'static) -> HttpResponse {
+    let events = stream::unfold(Box::pin(lines), |mut lines| async move {
+        let event = match tokio::time::timeout(JOURNAL_PING, lines.next()).await {
+            Ok(Some(Ok(line))) => SseEvent::data(line),
+            Ok(Some(Err(err))) => SseEvent::named("error", format!("{:#}", err)),
+            Ok(None) => return None,
+            Err(_) => SseEvent::named("ping", ""),
+        };
+        Some((event, lines))
+    });
+    sse(events)
 }
 
 #[derive(serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct HostIdParams {
+pub struct HostEventsParams {
     id: HostId,
+    /// the window to look back on, a day by default
+    hours: Option<u32>,
 }
 
-pub async fn ping_host(params: Query<HostIdParams>) -> ApiResult<()> {
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostTimeline {
+    pub events: Vec<HostEvent>,
+    /// percentage of the window the host was running, `None` if its state is not known
+    pub uptime: Option<f64>,
+    pub since: NaiveDateTime,
+}
+
+/// The state changes of the host within the window and its uptime over it
+pub async fn host_events(params: Query<HostEventsParams>) -> ApiResult<HostTimeline> {
+    let HostEventsParams { id, hours } = params.into_inner();
+    let now = chrono::Utc::now().naive_utc();
+    let since = now
+        .checked_sub_signed(chrono::Duration::hours(hours.unwrap_or(24).into()))
+        .ok_or_else(|| anyhow::anyhow!("hours is too large"))?;
+
+    let conn = &mut repositry::db_conn().await?;
+    let before = repositry::host_event::last_before(id, since, conn).await?;
+    let events = repositry::host_event::list_since(id, since, conn).await?;
+    let uptime = event::uptime(before.as_ref(), &events, since, now);
+    ApiResponse::ok(HostTimeline { events, uptime, since })
+}
+
+/// Push the current envoy binary to the host again
+pub async fn reinstall_envoy(params: Query<HostIdParams>) -> ApiResult<()> {
     let HostIdParams { id } = params.into_inner();
-    debug!(?id, "ping host");
     let conn = &mut repositry::db_conn().await?;
-    let mut host = repositry::host::get(id, conn)
+    let host = repositry::host::get(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host not found"))?;
+
+    ssh::reinstall_envoy(&host).await.map_err(anyhow::Error::from)?;
+    ApiResponse::ok(())
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpgradeEnvoysParams {
+    /// empty for every host
+    #[serde(default)]
+    pub hosts: Vec<HostId>,
+    pub concurrency: Option<usize>,
+}
+
+pub async fn upgrade_envoys(params: Json<UpgradeEnvoysParams>) -> ApiResult<EnvoyUpgradeId> {
+    let UpgradeEnvoysParams { hosts, concurrency } = params.into_inner();
+    let id = upgrade::upgrade_envoys(hosts, concurrency).await?;
+    ApiResponse::ok(id)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvoyUpgradeParams {
+    id: EnvoyUpgradeId,
+}
+
+pub async fn envoy_upgrade(params: Query<EnvoyUpgradeParams>) -> ApiResult<EnvoyUpgrade> {
+    let upgrade = upgrade::envoy_upgrade(params.id).ok_or_else(|| anyhow::anyhow!("envoy upgrade not found"))?;
+    ApiResponse::ok(upgrade)
+}
+
+pub async fn envoy_upgrades() -> ApiResult<Vec<EnvoyUpgrade>> {
+    ApiResponse::ok(upgrade::envoy_upgrades())
+}
+
+/// The apps installed on the host as recorded by the operator
+pub async fn host_instances(params: Query<HostIdParams>) -> ApiResult<Vec<AppInstance>> {
+    let HostIdParams { id } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let instances = repositry::app_instance::list_by_host(id, conn).await?;
+    ApiResponse::ok(instances)
+}
+
+/// Check the recorded instances of the host against what its envoy reports
+pub async fn sync_instances(params: Query<HostIdParams>) -> ApiResult<Vec<AppInstance>> {
+    let HostIdParams { id } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let host = repositry::host::get(id, conn)
         .await?
         .ok_or_else(|| anyhow::anyhow!("host not found"))?;
 
-    host.ping().await;
-    host::update(&host, conn).await?;
+    let instances = sync_host_instances(&host).await?;
+    ApiResponse::ok(instances)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateHostGroupParams {
+    pub name: String,
+    #[serde(default)]
+    pub hosts: Vec<HostId>,
+}
+
+pub async fn create_host_group(params: Json<CreateHostGroupParams>) -> ApiResult<HostGroupId> {
+    let CreateHostGroupParams { name, hosts } = params.into_inner();
+    let group = HostGroup::new(name, hosts);
+    let conn = &mut repositry::db_conn().await?;
+    repositry::host_group::save(&group, conn).await?;
+    ApiResponse::ok(group.id)
+}
+
+pub async fn host_group_list(params: Json<Pagination>) -> ApiResult<PageList<HostGroup>> {
+    let page = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    let groups = repositry::host_group::list(page, conn).await?;
+    ApiResponse::ok(groups)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupMembersParams {
+    pub id: HostGroupId,
+    pub hosts: Vec<HostId>,
+}
+
+/// Replace the hosts of a group
+pub async fn set_host_group_members(params: Json<GroupMembersParams>) -> ApiResult<()> {
+    let GroupMembersParams { id, hosts } = params.into_inner();
+    let conn = &mut repositry::db_conn().await?;
+    repositry::host_group::find(id, conn)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("host group not found"))?;
+    repositry::host_group::set_members(id, &hosts, conn).await?;
     ApiResponse::ok(())
 }
 
 pub async fn host_list(params: Json<Pagination>) -> ApiResult<PageList<Host>> {
     let page = params.into_inner();
     let conn = &mut repositry::db_conn().await?;
-    let mut hosts = repositry::host::list(page, conn).await?;
-    for host in hosts.data.iter_mut() {
-        host.ping().await;
-    }
+    let hosts = repositry::host::list(page, conn).await?;
 
     ApiResponse::ok(hosts)
 }
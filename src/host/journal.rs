@@ -0,0 +1,46 @@
+use anyhow::{Context, Result};
+use futures::{Stream, TryStreamExt};
+use volo_gen::av1::operator::JournalReq;
+
+use super::Host;
+
+/// Lines to start with when neither `since` nor `lines` is given
+const DEFAULT_LINES: u32 = 100;
+
+#[derive(Debug, Clone, Default)]
+pub struct JournalParams {
+    /// `av1-envoy` for the envoy itself
+    pub unit: String,
+    /// anything `journalctl --since` takes
+    pub since: Option<String>,
+    pub lines: Option<u32>,
+    pub follow: bool,
+}
+
+impl Host {
+    /// The journal of a unit on the host, one line per item. Following it ends only when the stream is dropped.
+    pub async fn journal(&self, params: JournalParams) -> Result<impl Stream<Item = Result<String>>> {
+        let JournalParams {
+            unit,
+            since,
+            lines,
+            follow,
+        } = params;
+        let lines = match (&since, lines) {
+            (_, Some(lines)) => lines,
+            (None, None) => DEFAULT_LINES,
+            (Some(_), None) => 0,
+        };
+        let req = JournalReq {
+            unit: unit.into(),
+            since: since.unwrap_or_default().into(),
+            lines,
+            follow,
+        };
+        let resp = self.client().journal(req).await.context("journal")?;
+        Ok(resp
+            .into_inner()
+            .map_ok(|line| line.line.to_string())
+            .map_err(|status| anyhow::Error::from(status).context("journal")))
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use futures::{Stream, TryStreamExt};
+use serde::Serialize;
+use volo_gen::av1::operator::{MetricsReq, MetricsSample};
+
+use super::Host;
+
+/// One sample of the load of a host
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostMetrics {
+    pub timestamp_ms: u64,
+    pub cpu_percent: f64,
+    pub mem_total_bytes: u64,
+    pub mem_available_bytes: u64,
+    pub load: [f64; 3],
+    /// counters since boot, the chart derives the rates
+    pub disk_read_bytes: u64,
+    pub disk_written_bytes: u64,
+    pub net_rx_bytes: u64,
+    pub net_tx_bytes: u64,
+}
+
+impl From<MetricsSample> for HostMetrics {
+    fn from(sample: MetricsSample) -> Self {
+        Self {
+            timestamp_ms: sample.timestamp_ms,
+            cpu_percent: sample.cpu_percent,
+            mem_total_bytes: sample.mem_total_bytes,
+            mem_available_bytes: sample.mem_available_bytes,
+            load: [sample.load1, sample.load5, sample.load15],
+            disk_read_bytes: sample.disk_read_bytes,
+            disk_written_bytes: sample.disk_written_bytes,
+            net_rx_bytes: sample.net_rx_bytes,
+            net_tx_bytes: sample.net_tx_bytes,
+        }
+    }
+}
+
+impl Host {
+    /// Samples from the envoy every `interval_ms`, the envoy stops sampling when the stream is dropped
+    pub async fn metrics(&self, interval_ms: u32) -> Result<impl Stream<Item = Result<HostMetrics>>> {
+        let resp = self.client().metrics(MetricsReq { interval_ms }).await.context("stream metrics")?;
+        Ok(resp
+            .into_inner()
+            .map_ok(HostMetrics::from)
+            .map_err(|status| anyhow::Error::from(status).context("stream metrics")))
+    }
+}
@@ -1,48 +1,154 @@
-use std::net::{IpAddr, SocketAddr};
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
 
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use diesel::{deserialize::FromSqlRow, expression::AsExpression, sql_types::SmallInt};
 use serde::{Deserialize, Serialize};
-use tracing::debug;
-use utils::id_new_type;
-use volo_gen::av1::operator::{NodeServiceClient, NodeServiceClientBuilder, Ping};
+use tracing::{debug, warn};
+use utils::{diesel_enum, id_new_type};
+use volo_gen::{
+    av1::operator::{HandshakeReq, NodeServiceClient, NodeServiceClientBuilder, Ping},
+    PROTOCOL_VERSION,
+};
+use volo_grpc::Code;
 
-use crate::settings::get_settings;
+use crate::{repositry, settings::get_settings};
 
+use self::{event::HostEvent, facts::HostFacts, ssh::SshParams};
+
+pub mod batch;
+pub mod code;
 pub mod convert;
+pub mod event;
+pub mod exec;
+pub mod facts;
+pub mod files;
+pub mod group;
 pub mod http_enpoint;
+pub mod journal;
+pub mod metrics;
+pub mod monitor;
+pub mod service;
 pub mod ssh;
+pub mod transport;
+pub mod upgrade;
 
 id_new_type!(HostId);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Host {
     pub id: HostId,
     pub name: String,
     pub ip: IpAddr,
     pub state: HostState,
+    pub ssh: SshParams,
+    /// as reported by the last handshake
+    pub envoy: Option<EnvoyInfo>,
+    /// the envoy speaks another protocol than the operator and needs an upgrade
+    #[serde(default)]
+    pub outdated: bool,
+    /// when the host last answered a health check
+    pub last_seen_at: Option<NaiveDateTime>,
+    /// failed health checks in a row
+    #[serde(default)]
+    pub failures: u32,
+    /// hardware and os, collected when the host comes up
+    pub facts: Option<HostFacts>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvoyInfo {
+    /// crate version of the envoy
+    pub version: String,
+    pub commit: String,
+    pub protocol: u32,
+}
+
+impl EnvoyInfo {
+    /// An envoy from before the handshake, it reports nothing about itself
+    fn legacy() -> Self {
+        Self {
+            version: String::new(),
+            commit: String::new(),
+            protocol: 0,
+        }
+    }
+
+    pub fn outdated(&self) -> bool {
+        self.protocol != PROTOCOL_VERSION
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = SmallInt)]
+#[repr(i16)]
 pub enum HostState {
     Running,
     Stopped,
     Disconnected,
 }
 
+diesel_enum!(HostState, max = HostState::Disconnected as i16);
+
 impl Host {
-    pub async fn ping(&mut self) {
+    /// Check the host is up and what envoy runs there, giving up after the monitor's timeout.
+    /// Returns the event to record when the state changed.
+    pub async fn ping(&mut self) -> Option<HostEvent> {
+        let was = self.state;
+        let timeout = get_settings().monitor.timeout_secs;
+        let reason = match tokio::time::timeout(Duration::from_secs(timeout), self.handshake()).await {
+            Ok(Ok(envoy)) => {
+                self.state = HostState::Running;
+                self.last_seen_at = Some(chrono::Utc::now().naive_utc());
+                self.failures = 0;
+                self.outdated = envoy.outdated();
+                self.envoy = Some(envoy);
+                "answered the health check".to_string()
+            }
+            result => {
+                let reason = match result {
+                    Ok(Err(err)) => format!("{:#}", err),
+                    _ => format!("no answer within {}s", timeout),
+                };
+                debug!(host = %self.id, reason, "ping host error");
+                self.state = HostState::Disconnected;
+                self.failures += 1;
+                reason
+            }
+        };
+        (self.state != was).then(|| HostEvent::new(self.id, Some(was), self.state, reason))
+    }
+
+    async fn handshake(&self) -> Result<EnvoyInfo> {
         let client = self.client();
-        let pong = client.ping(Ping { message: "ping".into() }).await;
-        if pong.is_err() {
-            debug!(?pong, "ping host error");
-            self.state = HostState::Disconnected;
-        } else {
-            self.state = HostState::Running;
+        let req = HandshakeReq {
+            protocol_version: PROTOCOL_VERSION,
+        };
+        match client.handshake(req).await {
+            Ok(resp) => {
+                let resp = resp.into_inner();
+                debug!(host = %self.id, hostname = %resp.hostname, uptime = resp.uptime_secs, "handshake");
+                Ok(EnvoyInfo {
+                    version: resp.crate_version.to_string(),
+                    commit: resp.git_commit.to_string(),
+                    protocol: resp.protocol_version,
+                })
+            }
+            // envoys from before the handshake only know ping
+            Err(status) if status.code() == Code::Unimplemented => {
+                client.ping(Ping { message: "ping".into() }).await.context("ping")?;
+                Ok(EnvoyInfo::legacy())
+            }
+            Err(status) => Err(status).context("handshake"),
         }
-
-        debug!(?pong);
     }
 
-    fn client(&self) -> NodeServiceClient {
+    pub fn client(&self) -> NodeServiceClient {
         let port = get_settings().envoy.port;
         let addr = SocketAddr::new(self.ip, port);
         debug!(?addr, "create grpc client");
@@ -50,3 +156,59 @@ impl Host {
         client
     }
 }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateHostParams {
+    pub name: String,
+    pub ip: IpAddr,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+/// Install the envoy on the host over ssh and record it
+pub async fn create_host(params: CreateHostParams) -> Result<Host> {
+    let CreateHostParams { name, ip, port, user, key } = params;
+
+    let mut builder = ssh::HostBuilder::new(name, ip);
+    if let Some(port) = port {
+        builder = builder.port(port);
+    }
+    if let Some(user) = user {
+        builder = builder.user(user.into());
+    }
+    if let Some(key) = key {
+        builder = builder.key(key);
+    }
+
+    let mut host = builder.build().await?;
+    host.ping().await;
+    if host.state == HostState::Running {
+        if let Err(err) = host.refresh_facts().await {
+            warn!(?err, host = %host.id, "collect facts failed");
+        }
+    }
+
+    let conn = &mut repositry::db_conn().await?;
+    repositry::host::save(&host, conn).await?;
+    let event = HostEvent::new(host.id, None, host.state, "created".to_string());
+    repositry::host_event::save(&event, conn).await?;
+
+    Ok(host)
+}
+
+/// Remove the envoy from the host and forget the host, `force` skips the remote cleanup for hosts that are gone
+pub async fn delete_host(id: HostId, force: bool) -> Result<()> {
+    let conn = &mut repositry::db_conn().await?;
+    let host = repositry::host::get(id, conn).await?.context("host not found")?;
+    if !force {
+        ssh::remove_envoy(&host).await?;
+    }
+    ssh::remove_key(&host).await?;
+    ssh::forget_host_key(&host).await?;
+    repositry::host::delete(id, conn).await
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::HashSet,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use anyhow::Result;
+use futures::future::join_all;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use crate::{repositry, settings::get_settings};
+
+use super::{event::HostEvent, files, Host, HostId, HostState};
+
+/// Check every host each `interval`, a zero interval leaves the stored states as they are
+pub fn spawn_monitor(interval: Duration) {
+    if interval.is_zero() {
+        info!("host monitor disabled");
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = check_all().await {
+                warn!(?err, "host check round failed");
+            }
+        }
+    });
+}
+
+/// Ping all hosts at once and store what was found
+pub async fn check_all() -> Result<()> {
+    let hosts = {
+        let conn = &mut repositry::db_conn().await?;
+        repositry::host::all(conn).await?
+    };
+
+    let checked = join_all(hosts.into_iter().map(check)).await;
+
+    let conn = &mut repositry::db_conn().await?;
+    for (host, event) in checked {
+        repositry::host::update_health(&host, conn).await?;
+        if let Some(event) = event {
+            repositry::host_event::save(&event, conn).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn check(mut host: Host) -> (Host, Option<HostEvent>) {
+    let event = host.ping().await;
+    if let Some(event) = &event {
+        info!(host = %host.id, from = ?event.from, to = ?event.state, reason = %event.reason, "host state changed");
+    }
+    // the hardware may have changed while the host was down
+    if host.state == HostState::Running && (event.is_some() || host.facts.is_none()) && !facts_unsupported(&host) {
+        let timeout = get_settings().monitor.timeout_secs;
+        match tokio::time::timeout(Duration::from_secs(timeout), host.refresh_facts()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) if files::unimplemented(&err) => {
+                info!(host = %host.id, "the envoy cannot collect facts, waiting for an upgrade");
+                unsupported().lock().unwrap().insert(envoy_key(&host));
+            }
+            Ok(Err(err)) => warn!(?err, host = %host.id, "collect facts failed"),
+            Err(_) => warn!(host = %host.id, "no facts within {}s", timeout),
+        }
+    }
+    (host, event)
+}
+
+/// Envoys known to lack `get_facts`, by host and envoy commit so an upgraded envoy is asked again
+fn unsupported() -> &'static Mutex<HashSet<(HostId, String)>> {
+    static UNSUPPORTED: OnceLock<Mutex<HashSet<(HostId, String)>>> = OnceLock::new();
+    UNSUPPORTED.get_or_init(Default::default)
+}
+
+fn envoy_key(host: &Host) -> (HostId, String) {
+    let commit = host.envoy.as_ref().map(|e| e.commit.clone()).unwrap_or_default();
+    (host.id, commit)
+}
+
+fn facts_unsupported(host: &Host) -> bool {
+    host.outdated || unsupported().lock().unwrap().contains(&envoy_key(host))
+}
@@ -0,0 +1,77 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use volo_gen::av1::operator::ServiceControlReq;
+
+use super::Host;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceAction {
+    Start,
+    Stop,
+    Restart,
+    Enable,
+    Disable,
+}
+
+impl ServiceAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            ServiceAction::Start => "start",
+            ServiceAction::Stop => "stop",
+            ServiceAction::Restart => "restart",
+            ServiceAction::Enable => "enable",
+            ServiceAction::Disable => "disable",
+        }
+    }
+}
+
+/// A systemd unit on a host as `systemctl show` reports it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceStatus {
+    pub unit: String,
+    /// `not-found` when there is no such unit
+    pub load_state: String,
+    pub active_state: String,
+    pub sub_state: String,
+    pub unit_file_state: String,
+    pub main_pid: Option<u32>,
+    /// restarts by systemd since the unit was last started by hand
+    pub restarts: u32,
+}
+
+impl From<volo_gen::av1::operator::ServiceStatus> for ServiceStatus {
+    fn from(status: volo_gen::av1::operator::ServiceStatus) -> Self {
+        Self {
+            unit: status.unit.to_string(),
+            load_state: status.load_state.to_string(),
+            active_state: status.active_state.to_string(),
+            sub_state: status.sub_state.to_string(),
+            unit_file_state: status.unit_file_state.to_string(),
+            main_pid: (status.main_pid != 0).then_some(status.main_pid),
+            restarts: status.restarts,
+        }
+    }
+}
+
+impl Host {
+    /// Run `action` on the unit through the envoy, or only read its status without one
+    pub async fn service_control(&self, unit: &str, action: Option<ServiceAction>) -> Result<ServiceStatus> {
+        if let Some(action) = action {
+            info!(host = %self.id, unit, action = action.as_str(), "service control");
+        }
+        let req = ServiceControlReq {
+            unit: unit.to_string().into(),
+            action: action.map(ServiceAction::as_str).unwrap_or_default().into(),
+        };
+        let resp = self
+            .client()
+            .service_control(req)
+            .await
+            .map_err(anyhow::Error::from)
+            .with_context(|| format!("control service {}", unit))?;
+        Ok(resp.into_inner().into())
+    }
+}
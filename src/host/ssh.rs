@@ -1,20 +1,167 @@
 use std::{
-    net::IpAddr,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
 };
 
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use tokio::{
     fs::{self, File},
     io::AsyncWriteExt,
 };
 use tracing::debug;
-use utils::async_cmd;
 use volo::FastStr;
 
 use crate::settings::{get_settings, CONFIG_DIR};
 
-use super::{Host, HostId};
+use super::{
+    transport::{SshError, SshTransport, Transport},
+    Host, HostId,
+};
+
+const ENVOY_BIN: &str = "/usr/local/bin/av1-envoy";
+const ENVOY_UNIT: &str = "/etc/systemd/system/av1-envoy.service";
+
+/// How the operator reaches a host over ssh
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SshParams {
+    pub port: u16,
+    pub user: String,
+    /// file name of the host's own key in the ssh key dir, `None` for the global key
+    pub key: Option<String>,
+}
+
+impl SshParams {
+    fn key_path(&self) -> PathBuf {
+        match &self.key {
+            Some(key) => get_settings().data_dir.ssh_key_dir().join(key),
+            None => HostBuilder::ssh_global_key_path(),
+        }
+    }
+}
+
+/// Open a ssh session to a created host with the credentials it was created with
+pub async fn connect(host: &Host) -> Result<SshTransport, SshError> {
+    let addr = SocketAddr::new(host.ip, host.ssh.port);
+    SshTransport::connect(addr, &host.ssh.user, &host.ssh.key_path(), &HostBuilder::known_hosts_path()).await
+}
+
+/// Undo what the bootstrap did: stop the envoy, remove its files and close its port to the operator
+pub async fn remove_envoy(host: &Host) -> Result<(), SshError> {
+    let mut ssh = connect(host).await?;
+    let removed = async {
+        run_cmd(&mut ssh, "systemctl disable --now av1-envoy.service || true").await?;
+        run_cmd(&mut ssh, &format!("rm -f {} {}", ENVOY_UNIT, ENVOY_BIN)).await?;
+        run_cmd(&mut ssh, "systemctl daemon-reload").await?;
+        let port = get_settings().envoy.port;
+        run_cmd(&mut ssh, &format!("firewall-cmd --remove-port {}/tcp", port)).await?;
+        let rule = envoy_port_rule(&mut ssh).await?;
+        run_cmd(&mut ssh, &format!("firewall-cmd --remove-rich-rule '{}'", rule)).await
+    }
+    .await;
+    if let Err(err) = ssh.close().await {
+        debug!(?err, "close ssh session");
+    }
+    removed
+}
+
+/// Push the current envoy binary to a created host and restart it there
+pub async fn reinstall_envoy(host: &Host) -> Result<(), SshError> {
+    let mut ssh = connect(host).await?;
+    let sent = send_envoy(&mut ssh).await;
+    if let Err(err) = ssh.close().await {
+        debug!(?err, "close ssh session");
+    }
+    sent
+}
+
+async fn send_envoy(ssh: &mut dyn Transport) -> Result<(), SshError> {
+    // open port to the operator only, the envoy runs anything it is asked to as root.
+    // older bootstraps opened it to everyone
+    let port = get_settings().envoy.port;
+    run_cmd(ssh, &format!("firewall-cmd --remove-port {}/tcp", port)).await?;
+    let rule = envoy_port_rule(ssh).await?;
+    run_cmd(ssh, &format!("firewall-cmd --add-rich-rule '{}'", rule)).await?;
+
+    // stop envoy
+    run_cmd(ssh, "systemctl stop av1-envoy || true").await?;
+
+    // sync app binary
+    let app_path = HostBuilder::envoy_bin_path();
+    ssh.upload(&app_path, ENVOY_BIN, 0o755).await?;
+    // sync systemd config
+    let service_path = Path::new(CONFIG_DIR).join("av1-envoy.service");
+    ssh.upload(&service_path, ENVOY_UNIT, 0o644).await?;
+
+    // start
+    run_cmd(ssh, "systemctl daemon-reload").await?;
+    run_cmd(ssh, "systemctl start av1-envoy.service").await?;
+    run_cmd(ssh, "systemctl enable av1-envoy.service").await?;
+
+    Ok(())
+}
+
+/// The firewalld rule letting only the operator reach the envoy port.
+/// The operator is wherever the ssh session comes from, as the host sees it.
+async fn envoy_port_rule(ssh: &mut dyn Transport) -> Result<String, SshError> {
+    let output = ssh.exec("echo $SSH_CLIENT").await?;
+    let ip: IpAddr = output
+        .stdout
+        .split_whitespace()
+        .next()
+        .and_then(|ip| ip.parse().ok())
+        .with_context(|| format!("no operator address in SSH_CLIENT {:?}", output.stdout.trim()))?;
+    let family = if ip.is_ipv4() { "ipv4" } else { "ipv6" };
+    let port = get_settings().envoy.port;
+    Ok(format!(
+        r#"rule family="{}" source address="{}" port port="{}" protocol="tcp" accept"#,
+        family, ip, port
+    ))
+}
+
+/// Delete the host's own key, the global key is shared and stays
+pub async fn remove_key(host: &Host) -> Result<()> {
+    let Some(key) = &host.ssh.key else { return Ok(()) };
+    let path = get_settings().data_dir.ssh_key_dir().join(key);
+    match fs::remove_file(&path).await {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err).with_context(|| format!("remove {:?}", path)),
+        _ => Ok(()),
+    }
+}
+
+/// Forget the key learned from the host, so a machine reinstalled at the same address can be added again
+pub async fn forget_host_key(host: &Host) -> Result<()> {
+    let path = HostBuilder::known_hosts_path();
+    let known_hosts = match fs::read_to_string(&path).await {
+        Ok(known_hosts) => known_hosts,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err).with_context(|| format!("read {:?}", path)),
+    };
+    let kept = without_host(&known_hosts, host.ip, host.ssh.port);
+    if kept.len() == known_hosts.len() {
+        return Ok(());
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, kept).await?;
+    fs::rename(&tmp_path, &path).await.with_context(|| format!("replace {:?}", path))
+}
+
+/// The known_hosts lines of every other host, in the format russh writes them
+fn without_host(known_hosts: &str, ip: IpAddr, port: u16) -> String {
+    let pattern = match port {
+        22 => ip.to_string(),
+        port => format!("[{}]:{}", ip, port),
+    };
+    known_hosts
+        .lines()
+        .filter(|line| {
+            let hosts = line.split_whitespace().next().unwrap_or_default();
+            !hosts.split(',').any(|h| h == pattern)
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
 
 pub struct HostBuilder {
     name: String,
@@ -50,129 +197,78 @@ impl HostBuilder {
         self
     }
 
-    pub async fn build(self) -> Result<Host> {
-        self.ssh_auth(self.key.as_deref()).await?;
-        self.send_envoy().await?;
+    pub async fn build(self) -> Result<Host, SshError> {
+        let mut ssh = self.ssh_auth(self.key.as_deref()).await?;
+        let sent = send_envoy(&mut ssh).await;
+        if let Err(err) = ssh.close().await {
+            debug!(?err, "close ssh session");
+        }
+        sent?;
 
         Ok(Host {
             id: HostId::next_id(),
             ip: self.ip,
             state: super::HostState::Running,
+            ssh: self.ssh_params(),
+            envoy: None,
+            outdated: false,
+            last_seen_at: None,
+            failures: 0,
+            facts: None,
             name: self.name,
         })
     }
 
-    async fn send_envoy(&self) -> Result<()> {
-        // open port
-        let port = get_settings().envoy.port;
-        self.run_ssh_cmd(&format!("firewall-cmd --add-port {}/tcp", port)).await?;
-
-        // stop envoy
-        self.run_ssh_cmd("systemctl stop av1-envoy || true").await?;
-
-        // sync app binary
-        let app_path = Self::envoy_bin_path();
-        self.scp(&app_path, Path::new("/usr/local/bin/av1-envoy")).await?;
-        // sync systemd config
-        let service_path = Path::new(CONFIG_DIR).join("av1-envoy.service");
-        self.scp(&service_path, Path::new("/etc/systemd/system/av1-envoy.service")).await?;
-
-        // start
-        self.run_ssh_cmd("systemctl daemon-reload").await?;
-        self.run_ssh_cmd("systemctl start av1-envoy.service").await?;
-        self.run_ssh_cmd("systemctl enable av1-envoy.service").await?;
-
-        Ok(())
-    }
-
     fn envoy_bin_path() -> PathBuf {
         let settings = &get_settings();
         settings.data_dir.envoy_bin_path()
     }
 
-    async fn ssh_auth(&self, key: Option<&str>) -> Result<()> {
+    async fn ssh_auth(&self, key: Option<&str>) -> Result<SshTransport, SshError> {
         // save key to tmp file
         let tmp_path = match key {
             Some(key) => {
                 let tmp_path = self.ssh_key_tmp_path();
-                let mut file = File::options().create(true).write(true).open(&tmp_path).await?;
+                let mut file = File::options()
+                    .create(true)
+                    .write(true)
+                    .truncate(true)
+                    .open(&tmp_path)
+                    .await
+                    .context("open key temp file")?;
                 file.write_all(key.as_bytes()).await.context("error: write key to temp file")?;
                 tmp_path
             }
             None => {
                 let global_key = Self::ssh_global_key_path();
                 if !global_key.exists() {
-                    anyhow::bail!("no global key");
+                    return Err(anyhow::anyhow!("no global key").into());
                 }
                 global_key
             }
         };
 
-        let conn_ok = Self::test_ssh_conn(&tmp_path, &*self.user, self.ip, self.port).await?;
-        if conn_ok {
+        let addr = SocketAddr::new(self.ip, self.port);
+        let ssh = SshTransport::connect(addr, &self.user, &tmp_path, &Self::known_hosts_path()).await?;
+        if key.is_some() {
             // move key file from tmp to data
             let key_path = self.ssh_key_path();
-            fs::rename(tmp_path, key_path).await?;
+            fs::rename(tmp_path, key_path).await.context("move key file")?;
         }
 
-        Ok(())
-    }
-
-    async fn test_ssh_conn(key: &Path, user: &str, ip: IpAddr, port: u16) -> Result<bool> {
-        let host_addr = format!("{}@{}", user, ip);
-        // TODO: 区分不同的错误，比如密钥错误，端口错误等
-        let t = async {
-            // ssh -q -o BatchMode=yes -o StrictHostKeyChecking=no -o ConnectTimeout=5 web15 "exit 0"
-            async_cmd!(
-                "ssh",
-                "-q",
-                "-o",
-                "BatchMode=yes",
-                "-o",
-                "StrictHostKeyChecking=no",
-                "-o",
-                "ConnectTimeout=5",
-                "-p",
-                port.to_string(),
-                "-i",
-                key,
-                host_addr,
-                "exit 0"
-            );
-            anyhow::Ok(())
-        };
-
-        Ok(t.await.is_ok())
-    }
-
-    async fn scp(&self, src: &Path, dst: &Path) -> Result<()> {
-        debug!(?src, ?dst, "scp file");
-        let dst = self.ssh_url() + ":" + &*dst.to_string_lossy();
-        let port = self.port.to_string();
-        let identity = self.ssh_key_path();
-        async_cmd!("scp", "-O", "-i", identity, "-r", "-P", port, src, dst);
-        Ok(())
+        Ok(ssh)
     }
 
-    async fn run_ssh_cmd(&self, cmd: &str) -> Result<()> {
-        debug!(cmd, "run ssh cmd");
-        let port = self.port.to_string();
-        let identity = self.ssh_key_path();
-        async_cmd!("ssh", "-i", identity, "-p", port, self.ssh_url(), cmd);
-        Ok(())
-    }
-
-    fn ssh_url(&self) -> String {
-        format!("{}@{}", self.user, self.ip)
+    fn ssh_params(&self) -> SshParams {
+        SshParams {
+            port: self.port,
+            user: self.user.to_string(),
+            key: self.key.as_ref().map(|_| format!("id_{}", self.ip)),
+        }
     }
 
     fn ssh_key_path(&self) -> PathBuf {
-        if self.key.is_none() {
-            return Self::ssh_global_key_path();
-        }
-        let mut dir = get_settings().data_dir.ssh_key_dir();
-        dir.push(format!("id_{}", self.ip));
-        dir
+        self.ssh_params().key_path()
     }
 
     fn ssh_key_tmp_path(&self) -> PathBuf {
@@ -184,6 +280,25 @@ impl HostBuilder {
     fn ssh_global_key_path() -> PathBuf {
         get_settings().data_dir.ssh_global_dir().join("id_rsa")
     }
+
+    /// Host keys seen on first contact, a later mismatch fails the bootstrap
+    fn known_hosts_path() -> PathBuf {
+        get_settings().data_dir.ssh_global_dir().join("known_hosts")
+    }
+}
+
+/// Run `cmd` on the host, a non-zero exit code is an error
+async fn run_cmd(ssh: &mut dyn Transport, cmd: &str) -> Result<(), SshError> {
+    let output = ssh.exec(cmd).await?;
+    debug!(cmd, code = output.exit_code, stdout = output.stdout.trim(), "ssh cmd done");
+    if !output.success() {
+        return Err(SshError::Command {
+            cmd: cmd.to_string(),
+            code: output.exit_code,
+            stderr: output.stderr.trim().to_string(),
+        });
+    }
+    Ok(())
 }
 
 pub fn init_dirs() -> Result<()> {
@@ -193,3 +308,22 @@ pub fn init_dirs() -> Result<()> {
     fs::create_dir_all(get_settings().data_dir.ssh_global_dir()).context("create ssh global dir")?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_without_host() {
+        let known_hosts = "10.0.0.1 ssh-ed25519 AAAA\n[10.0.0.1]:2222 ssh-ed25519 BBBB\n10.0.0.10 ssh-ed25519 CCCC\n";
+        let ip = "10.0.0.1".parse().unwrap();
+        assert_eq!(
+            without_host(known_hosts, ip, 22),
+            "[10.0.0.1]:2222 ssh-ed25519 BBBB\n10.0.0.10 ssh-ed25519 CCCC\n"
+        );
+        assert_eq!(
+            without_host(known_hosts, ip, 2222),
+            "10.0.0.1 ssh-ed25519 AAAA\n10.0.0.10 ssh-ed25519 CCCC\n"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use russh::{
+    client::{self, Handle},
+    keys::{check_known_hosts_path, known_hosts::learn_known_hosts_path, load_secret_key, PrivateKeyWithHashAlg, PublicKey},
+    ChannelMsg, Disconnect,
+};
+use russh_sftp::{client::SftpSession, protocol::FileAttributes};
+use tokio::{fs::File, io::AsyncWriteExt};
+use tracing::debug;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Why talking to a host over ssh failed, detailed enough to tell the operator what to fix
+#[derive(Debug, derive_more::Display)]
+pub enum SshError {
+    #[display(fmt = "host unreachable: {_0}")]
+    Unreachable(std::io::Error),
+    #[display(fmt = "connection refused, is sshd listening on the port?")]
+    Refused,
+    #[display(fmt = "connection timed out")]
+    Timeout,
+    #[display(fmt = "ssh key of user {_0} rejected")]
+    AuthRejected(String),
+    #[display(fmt = "host key does not match the known one")]
+    HostKeyMismatch,
+    #[display(fmt = "`{cmd}` exited with {code}: {stderr}")]
+    Command { cmd: String, code: u32, stderr: String },
+    #[display(fmt = "{_0:#}")]
+    Other(anyhow::Error),
+}
+
+impl std::error::Error for SshError {}
+
+impl From<russh::Error> for SshError {
+    fn from(err: russh::Error) -> Self {
+        match err {
+            russh::Error::IO(err) => match err.kind() {
+                ErrorKind::ConnectionRefused => Self::Refused,
+                ErrorKind::TimedOut => Self::Timeout,
+                _ => Self::Unreachable(err),
+            },
+            russh::Error::ConnectionTimeout => Self::Timeout,
+            russh::Error::KeyChanged { .. } | russh::Error::Keys(russh::keys::Error::KeyChanged { .. }) => Self::HostKeyMismatch,
+            err => Self::Other(err.into()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for SshError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Other(err)
+    }
+}
+
+/// A chunk of output of a remote command
+#[derive(Debug, Clone, Copy)]
+pub enum Output<'a> {
+    Stdout(&'a [u8]),
+    Stderr(&'a [u8]),
+}
+
+#[derive(Debug, Default)]
+pub struct CmdOutput {
+    pub exit_code: u32,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CmdOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+/// A way to run commands and copy files on a remote host
+#[async_trait]
+pub trait Transport: Send {
+    /// Run `cmd`, passing its output to `on_output` as it arrives, and return the exit code
+    async fn exec_streamed(&mut self, cmd: &str, on_output: &mut (dyn for<'o> FnMut(Output<'o>) + Send)) -> Result<u32>;
+
+    /// Copy the local file to `remote` and set its permission bits to `mode`
+    async fn upload(&mut self, local: &Path, remote: &str, mode: u32) -> Result<()>;
+
+    async fn close(&mut self) -> Result<()>;
+
+    /// Run `cmd` and collect its output
+    async fn exec(&mut self, cmd: &str) -> Result<CmdOutput> {
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+        let exit_code = self
+            .exec_streamed(cmd, &mut |output| match output {
+                Output::Stdout(data) => stdout.extend_from_slice(data),
+                Output::Stderr(data) => stderr.extend_from_slice(data),
+            })
+            .await?;
+        Ok(CmdOutput {
+            exit_code,
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        })
+    }
+}
+
+struct ClientHandler {
+    addr: SocketAddr,
+    known_hosts: PathBuf,
+}
+
+impl client::Handler for ClientHandler {
+    type Error = SshError;
+
+    async fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Self::Error> {
+        let host = self.addr.ip().to_string();
+        let port = self.addr.port();
+        match check_known_hosts_path(&host, port, key, &self.known_hosts) {
+            Ok(true) => Ok(true),
+            // first contact, trust the key from now on
+            Ok(false) => {
+                learn_known_hosts_path(&host, port, key, &self.known_hosts).context("learn host key")?;
+                Ok(true)
+            }
+            Err(russh::keys::Error::KeyChanged { .. }) => Err(SshError::HostKeyMismatch),
+            Err(err) => Err(SshError::Other(err.into())),
+        }
+    }
+}
+
+/// One authenticated ssh session, every command and upload runs on its own channel
+pub struct SshTransport {
+    handle: Handle<ClientHandler>,
+}
+
+impl SshTransport {
+    /// Connect and authenticate with `key`, the host key is checked against `known_hosts`
+    pub async fn connect(addr: SocketAddr, user: &str, key: &Path, known_hosts: &Path) -> Result<Self, SshError> {
+        let key = load_secret_key(key, None).context("load ssh key")?;
+        let config = Arc::new(client::Config::default());
+        let handler = ClientHandler {
+            addr,
+            known_hosts: known_hosts.to_path_buf(),
+        };
+
+        let mut handle = tokio::time::timeout(CONNECT_TIMEOUT, client::connect(config, addr, handler))
+            .await
+            .map_err(|_| SshError::Timeout)??;
+
+        let hash_alg = handle.best_supported_rsa_hash().await?.flatten();
+        let auth = handle
+            .authenticate_publickey(user, PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg))
+            .await?;
+        if !auth.success() {
+            return Err(SshError::AuthRejected(user.to_string()));
+        }
+        debug!(%addr, user, "ssh session established");
+
+        Ok(Self { handle })
+    }
+}
+
+#[async_trait]
+impl Transport for SshTransport {
+    async fn exec_streamed(&mut self, cmd: &str, on_output: &mut (dyn for<'o> FnMut(Output<'o>) + Send)) -> Result<u32> {
+        debug!(cmd, "run ssh cmd");
+        let mut channel = self.handle.channel_open_session().await?;
+        channel.exec(true, cmd).await?;
+
+        let mut exit_code = None;
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Data { ref data } => on_output(Output::Stdout(data)),
+                ChannelMsg::ExtendedData { ref data, ext: 1 } => on_output(Output::Stderr(data)),
+                ChannelMsg::ExitStatus { exit_status } => exit_code = Some(exit_status),
+                _ => {}
+            }
+        }
+
+        exit_code.with_context(|| format!("no exit status of `{}`", cmd))
+    }
+
+    async fn upload(&mut self, local: &Path, remote: &str, mode: u32) -> Result<()> {
+        debug!(?local, remote, "upload file");
+        let channel = self.handle.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+        let sftp = SftpSession::new(channel.into_stream()).await?;
+
+        let mut src = File::open(local).await.with_context(|| format!("open {:?}", local))?;
+        let mut dst = sftp.create(remote).await.with_context(|| format!("create {}", remote))?;
+        tokio::io::copy(&mut src, &mut dst).await.context("upload")?;
+        dst.shutdown().await.context("close remote file")?;
+
+        let attrs = FileAttributes {
+            permissions: Some(mode),
+            ..FileAttributes::empty()
+        };
+        sftp.set_metadata(remote, attrs).await.context("set file mode")?;
+        sftp.close().await?;
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.handle.disconnect(Disconnect::ByApplication, "", "en").await?;
+        Ok(())
+    }
+}
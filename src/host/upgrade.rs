@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{ensure, Context, Result};
+use chrono::NaiveDateTime;
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use tracing::{info, warn};
+use utils::id_new_type;
+
+use crate::repositry;
+
+use super::{ssh, Host, HostId};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+
+id_new_type!(EnvoyUpgradeId);
+
+/// Pushing the current envoy binary to a set of hosts, kept in memory only
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnvoyUpgrade {
+    pub id: EnvoyUpgradeId,
+    pub state: UpgradeState,
+    pub concurrency: usize,
+    pub hosts: Vec<HostUpgrade>,
+    pub started_at: NaiveDateTime,
+    pub ended_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostUpgrade {
+    pub host_id: HostId,
+    pub state: HostUpgradeState,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum UpgradeState {
+    Running,
+    /// every host runs the new envoy
+    Succeeded,
+    /// at least one host failed, the others were still upgraded
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum HostUpgradeState {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+fn upgrades() -> &'static Mutex<HashMap<EnvoyUpgradeId, EnvoyUpgrade>> {
+    static UPGRADES: OnceLock<Mutex<HashMap<EnvoyUpgradeId, EnvoyUpgrade>>> = OnceLock::new();
+    UPGRADES.get_or_init(Default::default)
+}
+
+pub fn envoy_upgrade(id: EnvoyUpgradeId) -> Option<EnvoyUpgrade> {
+    upgrades().lock().unwrap().get(&id).cloned()
+}
+
+/// Every upgrade since the operator started, the latest first
+pub fn envoy_upgrades() -> Vec<EnvoyUpgrade> {
+    let mut upgrades: Vec<_> = upgrades().lock().unwrap().values().cloned().collect();
+    upgrades.sort_by_key(|u| std::cmp::Reverse(u.started_at));
+    upgrades
+}
+
+/// Reinstall the envoy on `hosts`, or on every host when empty, at most `concurrency` at a time.
+/// Returns right away, the progress is read with [`envoy_upgrade`].
+pub async fn upgrade_envoys(hosts: Vec<HostId>, concurrency: Option<usize>) -> Result<EnvoyUpgradeId> {
+    let concurrency = concurrency.unwrap_or(DEFAULT_CONCURRENCY);
+    ensure!(concurrency > 0, "concurrency must be positive");
+
+    let conn = &mut repositry::db_conn().await?;
+    let hosts = if hosts.is_empty() {
+        repositry::host::all(conn).await?
+    } else {
+        let mut found = Vec::with_capacity(hosts.len());
+        for id in hosts {
+            found.push(
+                repositry::host::get(id, conn)
+                    .await?
+                    .with_context(|| format!("host {} not found", id))?,
+            );
+        }
+        found
+    };
+    ensure!(!hosts.is_empty(), "no host to upgrade");
+
+    let upgrade = EnvoyUpgrade {
+        id: EnvoyUpgradeId::next_id(),
+        state: UpgradeState::Running,
+        concurrency,
+        hosts: hosts
+            .iter()
+            .map(|h| HostUpgrade {
+                host_id: h.id,
+                state: HostUpgradeState::Pending,
+                error: None,
+            })
+            .collect(),
+        started_at: chrono::Utc::now().naive_utc(),
+        ended_at: None,
+    };
+    let id = upgrade.id;
+    upgrades().lock().unwrap().insert(id, upgrade);
+    info!(%id, hosts = hosts.len(), concurrency, "upgrading envoys");
+
+    tokio::spawn(run_upgrade(id, hosts, concurrency));
+    Ok(id)
+}
+
+async fn run_upgrade(id: EnvoyUpgradeId, hosts: Vec<Host>, concurrency: usize) {
+    stream::iter(hosts)
+        .for_each_concurrent(concurrency, |host| async move {
+            set_host_state(id, host.id, HostUpgradeState::Running, None);
+            match ssh::reinstall_envoy(&host).await {
+                Ok(()) => {
+                    info!(%id, host = %host.id, "envoy upgraded");
+                    set_host_state(id, host.id, HostUpgradeState::Succeeded, None);
+                }
+                Err(err) => {
+                    warn!(?err, %id, host = %host.id, "envoy upgrade failed");
+                    set_host_state(id, host.id, HostUpgradeState::Failed, Some(err.to_string()));
+                }
+            }
+        })
+        .await;
+
+    let mut upgrades = upgrades().lock().unwrap();
+    let Some(upgrade) = upgrades.get_mut(&id) else { return };
+    let failed = upgrade.hosts.iter().any(|h| h.state == HostUpgradeState::Failed);
+    upgrade.state = if failed { UpgradeState::Failed } else { UpgradeState::Succeeded };
+    upgrade.ended_at = Some(chrono::Utc::now().naive_utc());
+}
+
+fn set_host_state(id: EnvoyUpgradeId, host_id: HostId, state: HostUpgradeState, error: Option<String>) {
+    let mut upgrades = upgrades().lock().unwrap();
+    let host = upgrades
+        .get_mut(&id)
+        .and_then(|u| u.hosts.iter_mut().find(|h| h.host_id == host_id));
+    if let Some(host) = host {
+        host.state = state;
+        host.error = error;
+    }
+}
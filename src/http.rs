@@ -1,8 +1,15 @@
-use std::{fmt::Display, num::ParseIntError};
+use std::{convert::Infallible, fmt::Display, num::ParseIntError};
 
-use actix_web::{body::BoxBody, http::StatusCode, web::Json, HttpResponse, ResponseError};
+use actix_web::{
+    body::BoxBody,
+    http::{header, StatusCode},
+    web::{Bytes, Json},
+    HttpResponse, ResponseError,
+};
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use tracing::info;
+use utils::macros::code::Code;
 
 #[derive(Serialize)]
 pub struct ApiResponse<T> {
@@ -45,9 +52,20 @@ pub trait ErrorTrait: std::fmt::Debug + Display + 'static {}
 #[derive(derive_more::Display, Debug)]
 #[display(fmt = "error: {msg:?}")]
 pub struct ApiError {
+    code: u32,
     msg: Box<dyn ErrorTrait>,
 }
 
+impl ApiError {
+    /// Report `err` with the business code of `code` instead of the generic one
+    pub fn with_code<C: Code>(code: C, err: impl ErrorTrait) -> Self {
+        Self {
+            code: code.code(),
+            msg: Box::new(err),
+        }
+    }
+}
+
 impl ErrorTrait for anyhow::Error {}
 impl ErrorTrait for ParseIntError {}
 
@@ -56,7 +74,10 @@ where
     T: ErrorTrait,
 {
     fn from(value: T) -> Self {
-        Self { msg: Box::new(value) }
+        Self {
+            code: 1,
+            msg: Box::new(value),
+        }
     }
 }
 
@@ -68,7 +89,7 @@ impl ResponseError for ApiError {
     fn error_response(&self) -> HttpResponse<BoxBody> {
         info!(err = ?self.msg, "api error");
         let resp = ApiResponse::<()> {
-            status: 1,
+            status: self.code,
             err_msg: Some(self.to_string()),
             data: None,
         };
@@ -99,3 +120,54 @@ impl Pagination {
         self.page_size as i64
     }
 }
+
+/// One Server-Sent Event
+pub struct SseEvent {
+    event: Option<&'static str>,
+    data: String,
+}
+
+impl SseEvent {
+    pub fn data(data: impl Into<String>) -> Self {
+        Self {
+            event: None,
+            data: data.into(),
+        }
+    }
+
+    pub fn named(event: &'static str, data: impl Into<String>) -> Self {
+        Self {
+            event: Some(event),
+            data: data.into(),
+        }
+    }
+
+    fn encode(&self) -> Bytes {
+        let mut buf = String::new();
+        if let Some(event) = self.event {
+            buf += "event: ";
+            buf += event;
+            buf += "\n";
+        }
+        // a line break inside data would end the field, so every line gets its own one
+        for line in self.data.split('\n') {
+            buf += "data: ";
+            buf += line;
+            buf += "\n";
+        }
+        buf += "\n";
+        Bytes::from(buf)
+    }
+}
+
+/// Respond with a `text/event-stream` that ends when `events` ends
+pub fn sse<S>(events: S) -> HttpResponse
+where
+    S: Stream<Item = SseEvent> + 'static,
+{
+    let body = events.map(|event| Ok::<_, Infallible>(event.encode()));
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(body)
+}
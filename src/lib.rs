@@ -1,4 +1,4 @@
-use std::fs;
+use std::{fs, time::Duration};
 
 use actix_web::{dev::Server, web, App, HttpServer};
 use anyhow::Context;
@@ -9,6 +9,7 @@ use crate::{repositry::db_conn, settings::get_settings};
 mod application;
 mod host;
 mod http;
+mod manifest;
 mod repositry;
 mod schema;
 mod settings;
@@ -25,6 +26,18 @@ pub async fn init_global() -> anyhow::Result<()> {
     let conn = &mut db_conn().await?;
     conn.run_pending_migrations(MIGRATIONS).map_err(|e| anyhow::anyhow!(e))?;
 
+    let cancelled = repositry::build::cancel_all_unfinished(conn).await?;
+    if cancelled > 0 {
+        info!(cancelled, "cancelled builds interrupted by the last shutdown");
+    }
+    let cancelled = repositry::deployment::cancel_all_unfinished(conn).await?;
+    if cancelled > 0 {
+        info!(cancelled, "cancelled deployments interrupted by the last shutdown");
+    }
+
+    application::reconcile::spawn_reconciler(Duration::from_secs(settings.reconciler.interval_secs));
+    host::monitor::spawn_monitor(Duration::from_secs(settings.monitor.interval_secs));
+
     Ok(())
 }
 
@@ -33,6 +46,8 @@ fn init_work_dir() -> anyhow::Result<()> {
     let data_dir = &settings.data_dir;
     fs::create_dir_all(&**data_dir).context("create data dir")?;
     fs::create_dir_all(data_dir.envoy_dir()).context("create envoy dir")?;
+    fs::create_dir_all(data_dir.artifact_dir()).context("create artifact dir")?;
+    fs::create_dir_all(data_dir.upload_dir()).context("create upload dir")?;
 
     host::ssh::init_dirs()?;
     Ok(())
@@ -43,10 +58,12 @@ pub fn http_server() -> anyhow::Result<Server> {
     info!(?settings, "building http server. Powered by actix-web!");
 
     let server: Server = HttpServer::new(move || {
-        App::new()
+        // a second scope with the same prefix would never be matched, so modules only add routes
+        let api = web::scope("/api/operator")
             .configure(host::http_enpoint::config)
             .configure(application::http::config)
-            .route("/ping", web::get().to(|| async { "pong" }))
+            .configure(manifest::http::config);
+        App::new().service(api).route("/ping", web::get().to(|| async { "pong" }))
     })
     .bind((&*settings.bind, settings.port))?
     .run();
@@ -0,0 +1,48 @@
+use actix_web::web::{self, Query};
+use serde::Serialize;
+
+use crate::http::{ApiResponse, ApiResult};
+
+use super::{plan, Change, Format, Manifest};
+
+pub fn config(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.route("apply", web::post().to(apply));
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyParams {
+    #[serde(default)]
+    dry_run: bool,
+    /// also remove what the manifest does not list
+    #[serde(default)]
+    prune: bool,
+    #[serde(default = "default_format")]
+    format: Format,
+}
+
+fn default_format() -> Format {
+    Format::Toml
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyResult {
+    changes: Vec<Change>,
+    applied: bool,
+}
+
+/// The body is the manifest itself. With `dryRun` only the plan is returned.
+async fn apply(params: Query<ApplyParams>, body: String) -> ApiResult<ApplyResult> {
+    let ApplyParams { dry_run, prune, format } = params.into_inner();
+    let manifest = Manifest::parse(&body, format)?;
+    let plan = plan(manifest, prune).await?;
+    if dry_run {
+        let changes = plan.changes().cloned().collect();
+        return ApiResponse::ok(ApplyResult { changes, applied: false });
+    }
+
+    let changes = plan.changes().cloned().collect();
+    plan.apply().await?;
+    ApiResponse::ok(ApplyResult { changes, applied: true })
+}
@@ -0,0 +1,660 @@
+//! The fleet as one declarative document: hosts, host groups, apps and the versions they should run.
+//!
+//! A manifest is diffed against the sqlite state into a plan, which is then applied change by change.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    application::{
+        app_scripts, create_app, delete_app, reconcile::DesiredState, update_app, AppId, AppScripts, Application, CreateAppParams,
+    },
+    host::{
+        create_host, delete_host,
+        group::{HostGroup, HostGroupId},
+        CreateHostParams, Host, HostId,
+    },
+    repositry,
+};
+
+pub mod http;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    #[serde(default)]
+    pub hosts: Vec<CreateHostParams>,
+    #[serde(default)]
+    pub groups: Vec<ManifestGroup>,
+    #[serde(default)]
+    pub apps: Vec<ManifestApp>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestGroup {
+    pub name: String,
+    /// host names
+    #[serde(default)]
+    pub hosts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestApp {
+    #[serde(flatten)]
+    pub app: CreateAppParams,
+    #[serde(default)]
+    pub desired: Option<ManifestDesired>,
+}
+
+/// The version the reconciler should keep on a host group
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestDesired {
+    pub version: String,
+    pub group: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Format {
+    Toml,
+    Yaml,
+}
+
+impl Manifest {
+    pub fn parse(text: &str, format: Format) -> Result<Self> {
+        let manifest = match format {
+            Format::Toml => toml::from_str(text).context("invalid toml manifest")?,
+            Format::Yaml => serde_yaml::from_str(text).context("invalid yaml manifest")?,
+        };
+        Ok(manifest)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Action {
+    Create,
+    Update,
+    Remove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Kind {
+    Host,
+    HostGroup,
+    App,
+    DesiredState,
+}
+
+/// One line of a plan
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub action: Action,
+    pub kind: Kind,
+    pub name: String,
+    /// what differs, for updates
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<String>,
+}
+
+/// What applying a change runs. Names are resolved when it runs, so earlier changes can create them
+enum Op {
+    CreateHost(CreateHostParams),
+    UpdateHost(Box<Host>),
+    RemoveHost(HostId),
+    SetGroup {
+        id: Option<HostGroupId>,
+        name: String,
+        hosts: Vec<String>,
+    },
+    RemoveGroup(HostGroupId),
+    CreateApp(CreateAppParams),
+    UpdateApp(CreateAppParams),
+    RemoveApp(AppId),
+    SetDesired {
+        app: String,
+        hash: String,
+        group: String,
+    },
+    RemoveDesired(AppId),
+}
+
+pub struct Plan {
+    steps: Vec<(Change, Op)>,
+}
+
+impl Plan {
+    pub fn changes(&self) -> impl Iterator<Item = &Change> {
+        self.steps.iter().map(|(change, _)| change)
+    }
+
+    fn push(&mut self, action: Action, kind: Kind, name: &str, details: Vec<String>, op: Op) {
+        let change = Change {
+            action,
+            kind,
+            name: name.to_string(),
+            details,
+        };
+        self.steps.push((change, op));
+    }
+
+    /// Run the changes in order and stop at the first failing one, returns how many were applied
+    pub async fn apply(self) -> Result<usize> {
+        let mut applied = 0;
+        for (change, op) in self.steps {
+            info!(?change, "applying");
+            run(op)
+                .await
+                .with_context(|| format!("{:?} {:?} {}", change.action, change.kind, change.name))?;
+            applied += 1;
+        }
+        Ok(applied)
+    }
+}
+
+/// The state a manifest is diffed against
+struct Current {
+    hosts: Vec<Host>,
+    groups: Vec<HostGroup>,
+    apps: Vec<Application>,
+    desired: Vec<DesiredState>,
+    /// scripts of the existing apps the manifest names
+    scripts: HashMap<String, AppScripts>,
+    /// the desired versions of existing apps that were built
+    versions: HashSet<(AppId, String)>,
+}
+
+impl Current {
+    async fn load(manifest: &Manifest) -> Result<Self> {
+        let conn = &mut repositry::db_conn().await?;
+        let mut current = Current {
+            hosts: repositry::host::all(conn).await?,
+            groups: repositry::host_group::all(conn).await?,
+            apps: repositry::application::all(conn).await?,
+            desired: repositry::desired_state::list(conn).await?,
+            scripts: HashMap::new(),
+            versions: HashSet::new(),
+        };
+        for app in &current.apps {
+            let Some(wanted) = manifest.apps.iter().find(|a| a.app.name == app.name) else {
+                continue;
+            };
+            current.scripts.insert(app.name.clone(), app_scripts(&app.name).await?);
+            if let Some(desired) = &wanted.desired {
+                let version = repositry::application::find_version(&desired.version, conn).await?;
+                if version.is_some_and(|v| v.app_id == app.id) {
+                    current.versions.insert((app.id, desired.version.clone()));
+                }
+            }
+        }
+        Ok(current)
+    }
+}
+
+/// Diff the manifest against the current state. Things missing from the manifest are only
+/// removed with `prune`, so a partial manifest can be applied safely.
+pub async fn plan(manifest: Manifest, prune: bool) -> Result<Plan> {
+    let current = Current::load(&manifest).await?;
+    diff(manifest, prune, current)
+}
+
+fn diff(manifest: Manifest, prune: bool, current: Current) -> Result<Plan> {
+    let Current {
+        hosts,
+        groups,
+        apps,
+        desired,
+        mut scripts,
+        versions,
+    } = current;
+
+    ensure_unique(manifest.hosts.iter().map(|h| &h.name), "host")?;
+    ensure_unique(manifest.groups.iter().map(|g| &g.name), "host group")?;
+    ensure_unique(manifest.apps.iter().map(|a| &a.app.name), "app")?;
+
+    let mut plan = Plan { steps: Vec::new() };
+    let host_names: HashMap<_, _> = hosts.iter().map(|h| (h.name.as_str(), h)).collect();
+    let manifest_hosts: HashSet<_> = manifest.hosts.iter().map(|h| h.name.clone()).collect();
+    let known_host = |name: &str| manifest_hosts.contains(name) || (!prune && host_names.contains_key(name));
+
+    // hosts
+    for params in manifest.hosts {
+        match host_names.get(params.name.as_str()) {
+            None => {
+                let name = params.name.clone();
+                plan.push(Action::Create, Kind::Host, &name, vec![], Op::CreateHost(params));
+            }
+            Some(host) => {
+                // the envoy was bootstrapped and the host key learned at the old address
+                let port = params.port.unwrap_or(host.ssh.port);
+                ensure!(
+                    host.ip == params.ip && host.ssh.port == port,
+                    "host {} moves from {}:{} to {}:{}, remove and re-add the host",
+                    host.name,
+                    host.ip,
+                    host.ssh.port,
+                    params.ip,
+                    port
+                );
+                let mut updated = (*host).clone();
+                updated.ssh.user = params.user.clone().unwrap_or_else(|| host.ssh.user.clone());
+
+                let mut details = Vec::new();
+                if host.ssh.user != updated.ssh.user {
+                    details.push(format!("ssh user: {} -> {}", host.ssh.user, updated.ssh.user));
+                }
+                if !details.is_empty() {
+                    plan.push(Action::Update, Kind::Host, &params.name, details, Op::UpdateHost(Box::new(updated)));
+                }
+            }
+        }
+    }
+
+    // groups
+    let group_names: HashMap<_, _> = groups.iter().map(|g| (g.name.as_str(), g)).collect();
+    let host_name_of: HashMap<_, _> = hosts.iter().map(|h| (h.id, h.name.as_str())).collect();
+    let manifest_groups: HashSet<_> = manifest.groups.iter().map(|g| g.name.clone()).collect();
+    for group in manifest.groups {
+        if let Some(unknown) = group.hosts.iter().find(|h| !known_host(h)) {
+            bail!("host group {} contains unknown host {}", group.name, unknown);
+        }
+        let wanted: HashSet<_> = group.hosts.iter().map(String::as_str).collect();
+        match group_names.get(group.name.as_str()) {
+            None => {
+                let op = Op::SetGroup {
+                    id: None,
+                    name: group.name.clone(),
+                    hosts: group.hosts,
+                };
+                plan.push(Action::Create, Kind::HostGroup, &group.name, vec![], op);
+            }
+            Some(existing) => {
+                let current: HashSet<_> = existing.hosts.iter().filter_map(|id| host_name_of.get(id).copied()).collect();
+                let mut details: Vec<_> = wanted.difference(&current).map(|h| format!("+ {}", h)).collect();
+                details.extend(current.difference(&wanted).map(|h| format!("- {}", h)));
+                if details.is_empty() {
+                    continue;
+                }
+                details.sort();
+                let op = Op::SetGroup {
+                    id: Some(existing.id),
+                    name: group.name.clone(),
+                    hosts: group.hosts,
+                };
+                plan.push(Action::Update, Kind::HostGroup, &group.name, details, op);
+            }
+        }
+    }
+
+    // apps and their desired states
+    let app_names: HashMap<_, _> = apps.iter().map(|a| (a.name.as_str(), a)).collect();
+    let desired_of: HashMap<_, _> = desired.iter().map(|d| (d.app_id, d)).collect();
+    let group_name_of: HashMap<_, _> = groups.iter().map(|g| (g.id, g.name.as_str())).collect();
+    let manifest_apps: HashSet<_> = manifest.apps.iter().map(|a| a.app.name.clone()).collect();
+    for ManifestApp { app: params, desired } in manifest.apps {
+        let name = params.name.clone();
+        let existing = app_names.get(name.as_str());
+        if let Some(desired) = &desired {
+            let known_group = manifest_groups.contains(&desired.group) || (!prune && group_names.contains_key(desired.group.as_str()));
+            ensure!(known_group, "app {} wants unknown host group {}", name, desired.group);
+            // a new app has no versions yet, the reconciler waits for the build
+            if let Some(app) = existing {
+                let built = versions.contains(&(app.id, desired.version.clone()));
+                ensure!(built, "app {} has no version {}", name, desired.version);
+            }
+        }
+
+        match existing {
+            None => plan.push(Action::Create, Kind::App, &name, vec![], Op::CreateApp(params)),
+            Some(app) => {
+                let mut details = Vec::new();
+                if app.git != params.git {
+                    details.push(format!("git: {} -> {}", app.git, params.git));
+                }
+                let scripts = scripts.remove(&app.name).context("app scripts not loaded")?;
+                for (script, old, new) in [
+                    ("build", Some(&scripts.build), Some(&params.scripts.build)),
+                    ("install", Some(&scripts.install), Some(&params.scripts.install)),
+                    ("health", scripts.health.as_ref(), params.scripts.health.as_ref()),
+                ] {
+                    if old != new {
+                        details.push(format!("{} script changed", script));
+                    }
+                }
+                if !details.is_empty() {
+                    plan.push(Action::Update, Kind::App, &name, details, Op::UpdateApp(params));
+                }
+            }
+        }
+
+        let current = existing.and_then(|app| desired_of.get(&app.id));
+        match (desired, current) {
+            (Some(desired), current) => {
+                let current_group = current.and_then(|c| group_name_of.get(&c.group_id).copied());
+                let unchanged = current.is_some_and(|c| c.hash == desired.version) && current_group == Some(desired.group.as_str());
+                if unchanged {
+                    continue;
+                }
+                let action = if current.is_some() { Action::Update } else { Action::Create };
+                let details = vec![format!("{} on {}", desired.version, desired.group)];
+                let op = Op::SetDesired {
+                    app: name.clone(),
+                    hash: desired.version,
+                    group: desired.group,
+                };
+                plan.push(action, Kind::DesiredState, &name, details, op);
+            }
+            (None, Some(current)) if prune => {
+                plan.push(Action::Remove, Kind::DesiredState, &name, vec![], Op::RemoveDesired(current.app_id));
+            }
+            (None, _) => {}
+        }
+    }
+
+    if prune {
+        // dependents first: desired states and apps, then groups, then hosts
+        for app in apps.iter().filter(|a| !manifest_apps.contains(&a.name)) {
+            plan.push(Action::Remove, Kind::App, &app.name, vec![], Op::RemoveApp(app.id));
+        }
+        for group in groups.iter().filter(|g| !manifest_groups.contains(&g.name)) {
+            plan.push(Action::Remove, Kind::HostGroup, &group.name, vec![], Op::RemoveGroup(group.id));
+        }
+        for host in hosts.iter().filter(|h| !manifest_hosts.contains(h.name.as_str())) {
+            plan.push(Action::Remove, Kind::Host, &host.name, vec![], Op::RemoveHost(host.id));
+        }
+    }
+
+    Ok(plan)
+}
+
+fn ensure_unique<'a>(names: impl Iterator<Item = &'a String>, kind: &str) -> Result<()> {
+    let mut seen = HashSet::new();
+    for name in names {
+        ensure!(seen.insert(name), "duplicate {} {}", kind, name);
+    }
+    Ok(())
+}
+
+async fn run(op: Op) -> Result<()> {
+    match op {
+        Op::CreateHost(params) => {
+            create_host(params).await?;
+        }
+        Op::UpdateHost(host) => {
+            let conn = &mut repositry::db_conn().await?;
+            repositry::host::update(&host, conn).await?;
+        }
+        Op::RemoveHost(id) => delete_host(id, false).await?,
+        Op::SetGroup { id, name, hosts } => {
+            let conn = &mut repositry::db_conn().await?;
+            let by_name: HashMap<_, _> = repositry::host::all(conn).await?.into_iter().map(|h| (h.name, h.id)).collect();
+            let hosts = hosts
+                .iter()
+                .map(|name| by_name.get(name).copied().with_context(|| format!("host {} not found", name)))
+                .collect::<Result<Vec<_>>>()?;
+            match id {
+                Some(id) => repositry::host_group::set_members(id, &hosts, conn).await?,
+                None => repositry::host_group::save(&HostGroup::new(name, hosts), conn).await?,
+            }
+        }
+        Op::RemoveGroup(id) => {
+            let conn = &mut repositry::db_conn().await?;
+            repositry::host_group::delete(id, conn).await?;
+        }
+        Op::CreateApp(params) => create_app(params).await?,
+        Op::UpdateApp(params) => update_app(params).await?,
+        Op::RemoveApp(id) => delete_app(id).await?,
+        Op::SetDesired { app, hash, group } => {
+            let conn = &mut repositry::db_conn().await?;
+            let app = repositry::application::find_by_name(&app, conn).await?.context("app not found")?;
+            let group = repositry::host_group::all(conn)
+                .await?
+                .into_iter()
+                .find(|g| g.name == group)
+                .context("host group not found")?;
+            let desired = DesiredState {
+                app_id: app.id,
+                hash,
+                group_id: group.id,
+            };
+            repositry::desired_state::upsert(&desired, conn).await?;
+        }
+        Op::RemoveDesired(app_id) => {
+            let conn = &mut repositry::db_conn().await?;
+            repositry::desired_state::delete(app_id, conn).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        host::{ssh::SshParams, HostState},
+        repositry::application::ApplicaionPo,
+    };
+
+    use super::*;
+
+    const MANIFEST: &str = r#"
+        [[hosts]]
+        name = "a"
+        ip = "10.0.0.1"
+
+        [[hosts]]
+        name = "b"
+        ip = "10.0.0.2"
+
+        [[groups]]
+        name = "web"
+        hosts = ["a", "b"]
+
+        [[apps]]
+        name = "demo"
+        git = "https://example.com/demo.git"
+        desired = { version = "v1", group = "web" }
+        scripts = { build = "make", install = "make install" }
+    "#;
+
+    fn host(id: i64, name: &str, ip: &str) -> Host {
+        Host {
+            id: HostId::from(id),
+            name: name.to_string(),
+            ip: ip.parse().unwrap(),
+            state: HostState::Running,
+            ssh: SshParams {
+                port: 22,
+                user: "root".to_string(),
+                key: None,
+            },
+            envoy: None,
+            outdated: false,
+            last_seen_at: None,
+            failures: 0,
+            facts: None,
+        }
+    }
+
+    fn app(id: i64, name: &str) -> Application {
+        let po = ApplicaionPo {
+            id: AppId::from(id),
+            name: name.to_string().into(),
+            git_url: format!("https://example.com/{}.git", name).into(),
+        };
+        Application::try_from((po, Vec::new())).unwrap()
+    }
+
+    fn scripts() -> AppScripts {
+        AppScripts {
+            build: "make".to_string(),
+            install: "make install".to_string(),
+            health: None,
+        }
+    }
+
+    /// What the manifest above created
+    fn current() -> Current {
+        Current {
+            hosts: vec![host(1, "a", "10.0.0.1"), host(2, "b", "10.0.0.2")],
+            groups: vec![HostGroup {
+                id: HostGroupId::from(1),
+                name: "web".to_string(),
+                hosts: vec![HostId::from(1), HostId::from(2)],
+            }],
+            apps: vec![app(1, "demo")],
+            desired: vec![DesiredState {
+                app_id: AppId::from(1),
+                hash: "v1".to_string(),
+                group_id: HostGroupId::from(1),
+            }],
+            scripts: HashMap::from([("demo".to_string(), scripts())]),
+            versions: HashSet::from([(AppId::from(1), "v1".to_string()), (AppId::from(1), "v2".to_string())]),
+        }
+    }
+
+    fn empty() -> Current {
+        Current {
+            hosts: Vec::new(),
+            groups: Vec::new(),
+            apps: Vec::new(),
+            desired: Vec::new(),
+            scripts: HashMap::new(),
+            versions: HashSet::new(),
+        }
+    }
+
+    fn changes(plan: &Plan) -> Vec<(Action, Kind, &str)> {
+        plan.changes().map(|c| (c.action, c.kind, c.name.as_str())).collect()
+    }
+
+    fn manifest(text: &str) -> Manifest {
+        Manifest::parse(text, Format::Toml).unwrap()
+    }
+
+    #[test]
+    fn t_plan_create() {
+        let plan = diff(manifest(MANIFEST), false, empty()).unwrap();
+        assert_eq!(
+            changes(&plan),
+            [
+                (Action::Create, Kind::Host, "a"),
+                (Action::Create, Kind::Host, "b"),
+                (Action::Create, Kind::HostGroup, "web"),
+                (Action::Create, Kind::App, "demo"),
+                (Action::Create, Kind::DesiredState, "demo"),
+            ]
+        );
+        assert_eq!(diff(manifest(MANIFEST), true, current()).unwrap().changes().count(), 0);
+    }
+
+    #[test]
+    fn t_plan_update() {
+        let text = MANIFEST
+            .replace(r#"ip = "10.0.0.2""#, "ip = \"10.0.0.2\"\nuser = \"deploy\"")
+            .replace(r#"hosts = ["a", "b"]"#, r#"hosts = ["a", "c"]"#)
+            .replace("https://example.com/demo.git", "https://example.com/demo2.git")
+            .replace(r#"version = "v1""#, r#"version = "v2""#)
+            + r#"
+                [[hosts]]
+                name = "c"
+                ip = "10.0.0.3"
+            "#;
+        let plan = diff(manifest(&text), false, current()).unwrap();
+        assert_eq!(
+            changes(&plan),
+            [
+                (Action::Update, Kind::Host, "b"),
+                (Action::Create, Kind::Host, "c"),
+                (Action::Update, Kind::HostGroup, "web"),
+                (Action::Update, Kind::App, "demo"),
+                (Action::Update, Kind::DesiredState, "demo"),
+            ]
+        );
+        let details: Vec<_> = plan.changes().map(|c| c.details.join(", ")).collect();
+        assert_eq!(
+            details,
+            [
+                "ssh user: root -> deploy",
+                "",
+                "+ c, - b",
+                "git: https://example.com/demo.git -> https://example.com/demo2.git",
+                "v2 on web",
+            ]
+        );
+
+        let text = MANIFEST.replace(r#"ip = "10.0.0.2""#, "ip = \"10.0.0.2\"\nport = 2222");
+        let err = diff(manifest(&text), false, current()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "host b moves from 10.0.0.2:22 to 10.0.0.2:2222, remove and re-add the host"
+        );
+    }
+
+    #[test]
+    fn t_plan_prune() {
+        let text = r#"
+            [[hosts]]
+            name = "a"
+            ip = "10.0.0.1"
+
+            [[apps]]
+            name = "demo"
+            git = "https://example.com/demo.git"
+            scripts = { build = "make", install = "make install" }
+        "#;
+        let mut current = current();
+        current.apps.push(app(2, "other"));
+        // a partial manifest leaves the rest alone
+        let plan = diff(manifest(text), false, current).unwrap();
+        assert_eq!(plan.changes().count(), 0);
+
+        let mut current = self::current();
+        current.apps.push(app(2, "other"));
+        let plan = diff(manifest(text), true, current).unwrap();
+        // dependents go first
+        assert_eq!(
+            changes(&plan),
+            [
+                (Action::Remove, Kind::DesiredState, "demo"),
+                (Action::Remove, Kind::App, "other"),
+                (Action::Remove, Kind::HostGroup, "web"),
+                (Action::Remove, Kind::Host, "b"),
+            ]
+        );
+    }
+
+    #[test]
+    fn t_plan_unknown() {
+        let text = r#"
+            [[groups]]
+            name = "web"
+            hosts = ["a", "b"]
+
+            [[apps]]
+            name = "demo"
+            git = "https://example.com/demo.git"
+            desired = { version = "v1", group = "web" }
+            scripts = { build = "make", install = "make install" }
+        "#;
+        // the hosts exist unless pruned
+        assert!(diff(manifest(text), false, current()).is_ok());
+        let err = diff(manifest(text), true, current()).err().unwrap();
+        assert_eq!(err.to_string(), "host group web contains unknown host a");
+
+        let text = text.replace(r#"group = "web""#, r#"group = "db""#);
+        let err = diff(manifest(&text), false, current()).err().unwrap();
+        assert_eq!(err.to_string(), "app demo wants unknown host group db");
+
+        let text = MANIFEST.replace(r#"version = "v1""#, r#"version = "v3""#);
+        let err = diff(manifest(&text), false, current()).err().unwrap();
+        assert_eq!(err.to_string(), "app demo has no version v3");
+    }
+}
@@ -0,0 +1,74 @@
+use std::borrow::Cow;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::{prelude::*, upsert::excluded};
+
+use crate::{
+    application::{
+        instance::{AppInstance, InstanceState},
+        AppId,
+    },
+    host::HostId,
+    schema::app_instances,
+};
+
+use super::SqliteConn;
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Insertable, AsChangeset)]
+#[diesel(table_name = app_instances)]
+#[diesel(primary_key(app_id, host_id))]
+pub struct AppInstancePo<'a> {
+    pub app_id: AppId,
+    pub host_id: HostId,
+    pub hash: Cow<'a, str>,
+    pub install_dir: Option<Cow<'a, str>>,
+    pub state: InstanceState,
+    pub reported_hash: Option<Cow<'a, str>>,
+    pub reported_at: Option<NaiveDateTime>,
+}
+
+pub async fn upsert(instance: &AppInstance, conn: &mut SqliteConn) -> Result<()> {
+    let instance = AppInstancePo::from(instance);
+    diesel::insert_into(app_instances::table)
+        .values(&instance)
+        .on_conflict((app_instances::app_id, app_instances::host_id))
+        .do_update()
+        .set((
+            app_instances::hash.eq(excluded(app_instances::hash)),
+            app_instances::install_dir.eq(excluded(app_instances::install_dir)),
+            app_instances::state.eq(excluded(app_instances::state)),
+            app_instances::reported_hash.eq(excluded(app_instances::reported_hash)),
+            app_instances::reported_at.eq(excluded(app_instances::reported_at)),
+            app_instances::updated_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+pub async fn find(app_id: AppId, host_id: HostId, conn: &mut SqliteConn) -> Result<Option<AppInstance>> {
+    let instance = app_instances::table
+        .select(AppInstancePo::as_select())
+        .find((app_id, host_id))
+        .first(conn)
+        .optional()?;
+    instance.map(AppInstance::try_from).transpose()
+}
+
+pub async fn list_by_app(app_id: AppId, conn: &mut SqliteConn) -> Result<Vec<AppInstance>> {
+    let instances: Vec<AppInstancePo> = app_instances::table
+        .select(AppInstancePo::as_select())
+        .filter(app_instances::app_id.eq(app_id))
+        .order(app_instances::host_id)
+        .load(conn)?;
+    instances.into_iter().map(AppInstance::try_from).collect()
+}
+
+pub async fn list_by_host(host_id: HostId, conn: &mut SqliteConn) -> Result<Vec<AppInstance>> {
+    let instances: Vec<AppInstancePo> = app_instances::table
+        .select(AppInstancePo::as_select())
+        .filter(app_instances::host_id.eq(host_id))
+        .order(app_instances::app_id)
+        .load(conn)?;
+    instances.into_iter().map(AppInstance::try_from).collect()
+}
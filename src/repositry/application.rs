@@ -3,7 +3,7 @@ use std::{borrow::Cow, collections::HashMap};
 use crate::{
     application::{AppId, Application},
     http::Pagination,
-    schema::{app_versions, applications},
+    schema::{app_instances, app_versions, applications, desired_states},
 };
 use anyhow::Result;
 use diesel::prelude::*;
@@ -25,6 +25,8 @@ pub struct ApplicaionPo<'a> {
 pub struct AppVersionPo<'a> {
     pub hash: Cow<'a, str>,
     pub app_id: AppId,
+    /// sha256 of the packed build output
+    pub artifact: Option<Cow<'a, str>>,
 }
 
 pub async fn save(app: &Application, conn: &mut SqliteConn) -> Result<()> {
@@ -33,11 +35,47 @@ pub async fn save(app: &Application, conn: &mut SqliteConn) -> Result<()> {
     Ok(())
 }
 
+pub async fn update(app: &Application, conn: &mut SqliteConn) -> Result<()> {
+    let app = ApplicaionPo::from(app);
+    diesel::update(applications::table)
+        .filter(applications::id.eq(app.id))
+        .set(app)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Forget the app with its versions, instances and desired state, build and deployment history is kept
+pub async fn delete(id: AppId, conn: &mut SqliteConn) -> Result<()> {
+    conn.transaction(|conn| {
+        diesel::delete(app_versions::table.filter(app_versions::app_id.eq(id))).execute(conn)?;
+        diesel::delete(app_instances::table.filter(app_instances::app_id.eq(id))).execute(conn)?;
+        diesel::delete(desired_states::table.find(id)).execute(conn)?;
+        diesel::delete(applications::table.find(id)).execute(conn)?;
+        diesel::QueryResult::Ok(())
+    })?;
+    Ok(())
+}
+
+/// Rebuilding an already recorded commit replaces its artifact with the new one
 pub async fn new_version(version: AppVersionPo<'_>, conn: &mut SqliteConn) -> Result<()> {
-    diesel::insert_into(app_versions::table).values(version).execute(conn)?;
+    diesel::insert_into(app_versions::table)
+        .values(&version)
+        .on_conflict(app_versions::hash)
+        .do_update()
+        .set(app_versions::artifact.eq(&version.artifact))
+        .execute(conn)?;
     Ok(())
 }
 
+pub async fn find_version(hash: &str, conn: &mut SqliteConn) -> Result<Option<AppVersionPo<'static>>> {
+    let version = app_versions::table
+        .select(AppVersionPo::as_select())
+        .find(hash)
+        .first(conn)
+        .optional()?;
+    Ok(version)
+}
+
 pub async fn find(id: AppId, conn: &mut SqliteConn) -> Result<Option<Application>> {
     let app = applications::table
         .select(ApplicaionPo::as_select())
@@ -55,6 +93,27 @@ pub async fn find(id: AppId, conn: &mut SqliteConn) -> Result<Option<Application
     Ok(Some(app))
 }
 
+pub async fn find_by_name(name: &str, conn: &mut SqliteConn) -> Result<Option<Application>> {
+    let id = applications::table
+        .select(applications::id)
+        .filter(applications::name.eq(name))
+        .first::<AppId>(conn)
+        .optional()?;
+    match id {
+        Some(id) => find(id, conn).await,
+        None => Ok(None),
+    }
+}
+
+/// Every app, without its versions
+pub async fn all(conn: &mut SqliteConn) -> Result<Vec<Application>> {
+    let apps: Vec<ApplicaionPo> = applications::table
+        .select(ApplicaionPo::as_select())
+        .order(applications::name)
+        .load(conn)?;
+    apps.into_iter().map(|app| Application::try_from((app, Vec::new()))).collect()
+}
+
 pub async fn list(page: Pagination, conn: &mut SqliteConn) -> Result<PageList<Application>> {
     let apps: Vec<(ApplicaionPo, i64)> = applications::table
         .select(ApplicaionPo::as_select())
@@ -0,0 +1,81 @@
+use std::borrow::Cow;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    application::{AppId, Build, BuildId, BuildState},
+    http::Pagination,
+    schema::builds,
+};
+
+use super::{PageList, Paginate, SqliteConn};
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Insertable, AsChangeset)]
+#[diesel(table_name = builds)]
+pub struct BuildPo<'a> {
+    pub id: BuildId,
+    pub app_id: AppId,
+    pub hash: Cow<'a, str>,
+    pub commit_hash: Option<Cow<'a, str>>,
+    pub state: BuildState,
+    pub exit_code: Option<i32>,
+    pub started_at: Option<NaiveDateTime>,
+    pub ended_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+const UNFINISHED: [BuildState; 2] = [BuildState::Queued, BuildState::Running];
+
+pub async fn save(build: &Build, conn: &mut SqliteConn) -> Result<()> {
+    let build = BuildPo::from(build);
+    diesel::insert_into(builds::table).values(build).execute(conn)?;
+    Ok(())
+}
+
+pub async fn update(build: &Build, conn: &mut SqliteConn) -> Result<()> {
+    let build = BuildPo::from(build);
+    diesel::update(builds::table)
+        .filter(builds::id.eq(build.id))
+        .set(build)
+        .execute(conn)?;
+    Ok(())
+}
+
+pub async fn find(id: BuildId, conn: &mut SqliteConn) -> Result<Option<Build>> {
+    let build = builds::table.select(BuildPo::as_select()).find(id).first(conn).optional()?;
+    build.map(Build::try_from).transpose()
+}
+
+pub async fn list(app_id: AppId, page: Pagination, conn: &mut SqliteConn) -> Result<PageList<Build>> {
+    let builds: Vec<(BuildPo, i64)> = builds::table
+        .select(BuildPo::as_select())
+        .filter(builds::app_id.eq(app_id))
+        .order(builds::created_at.desc())
+        .paginate(page.offset(), page.limit())
+        .load(conn)?;
+    PageList::from(builds).try_convert()
+}
+
+/// Mark a build that is still queued or running as finished with `state`
+pub async fn finish_unfinished(id: BuildId, state: BuildState, conn: &mut SqliteConn) -> Result<()> {
+    diesel::update(builds::table)
+        .filter(builds::id.eq(id))
+        .filter(builds::state.eq_any(UNFINISHED))
+        .set((builds::state.eq(state), builds::ended_at.eq(chrono::Utc::now().naive_utc())))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Build jobs live in memory, nothing is left to finish the unfinished ones after a restart
+pub async fn cancel_all_unfinished(conn: &mut SqliteConn) -> Result<usize> {
+    let count = diesel::update(builds::table)
+        .filter(builds::state.eq_any(UNFINISHED))
+        .set((
+            builds::state.eq(BuildState::Cancelled),
+            builds::ended_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+    Ok(count)
+}
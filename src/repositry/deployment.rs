@@ -0,0 +1,243 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    application::{
+        deploy::{Deployment, DeploymentId, DeploymentState, HostDeployState},
+        AppId,
+    },
+    host::HostId,
+    http::Pagination,
+    schema::{deployment_hosts, deployments},
+};
+
+use super::{PageList, Paginate, SqliteConn};
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Insertable, AsChangeset)]
+#[diesel(table_name = deployments)]
+pub struct DeploymentPo<'a> {
+    pub id: DeploymentId,
+    pub app_id: AppId,
+    pub hash: Cow<'a, str>,
+    pub triggered_by: Cow<'a, str>,
+    pub rollback_of: Option<DeploymentId>,
+    /// `DeployStrategy` as json
+    pub strategy: Cow<'a, str>,
+    pub state: DeploymentState,
+    pub started_at: NaiveDateTime,
+    pub ended_at: Option<NaiveDateTime>,
+}
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Insertable, AsChangeset)]
+#[diesel(table_name = deployment_hosts)]
+#[diesel(primary_key(deployment_id, host_id))]
+pub struct DeploymentHostPo<'a> {
+    pub deployment_id: DeploymentId,
+    pub host_id: HostId,
+    pub state: HostDeployState,
+    pub install_dir: Option<Cow<'a, str>>,
+    pub exit_code: Option<i32>,
+    pub stdout: Cow<'a, str>,
+    pub stderr: Cow<'a, str>,
+    pub error: Option<Cow<'a, str>>,
+}
+
+pub async fn save(deployment: &Deployment, conn: &mut SqliteConn) -> Result<()> {
+    let (deployment, hosts) = <(DeploymentPo, Vec<DeploymentHostPo>)>::from(deployment);
+    conn.transaction(|conn| {
+        diesel::insert_into(deployments::table).values(deployment).execute(conn)?;
+        diesel::insert_into(deployment_hosts::table).values(hosts).execute(conn)?;
+        diesel::QueryResult::Ok(())
+    })?;
+    Ok(())
+}
+
+pub async fn update(deployment: &Deployment, conn: &mut SqliteConn) -> Result<()> {
+    let (deployment, hosts) = <(DeploymentPo, Vec<DeploymentHostPo>)>::from(deployment);
+    conn.transaction(|conn| {
+        diesel::update(deployments::table)
+            .filter(deployments::id.eq(deployment.id))
+            .set(&deployment)
+            .execute(conn)?;
+        for host in hosts {
+            diesel::update(deployment_hosts::table)
+                .filter(deployment_hosts::deployment_id.eq(host.deployment_id))
+                .filter(deployment_hosts::host_id.eq(host.host_id))
+                .set(&host)
+                .execute(conn)?;
+        }
+        diesel::QueryResult::Ok(())
+    })?;
+    Ok(())
+}
+
+const UNFINISHED: [DeploymentState; 2] = [DeploymentState::Running, DeploymentState::WaitingApproval];
+
+/// Rollouts live in memory, nothing is left to finish the unfinished ones after a restart
+pub async fn cancel_all_unfinished(conn: &mut SqliteConn) -> Result<usize> {
+    let count = conn.transaction(|conn| {
+        let ids = deployments::table
+            .select(deployments::id)
+            .filter(deployments::state.eq_any(UNFINISHED))
+            .load::<DeploymentId>(conn)?;
+        diesel::update(deployment_hosts::table)
+            .filter(deployment_hosts::deployment_id.eq_any(&ids))
+            .filter(deployment_hosts::state.eq(HostDeployState::Pending))
+            .set(deployment_hosts::state.eq(HostDeployState::Skipped))
+            .execute(conn)?;
+        diesel::update(deployments::table)
+            .filter(deployments::id.eq_any(&ids))
+            .set((
+                deployments::state.eq(DeploymentState::Cancelled),
+                deployments::ended_at.eq(chrono::Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+    })?;
+    Ok(count)
+}
+
+pub async fn find(id: DeploymentId, conn: &mut SqliteConn) -> Result<Option<Deployment>> {
+    let deployment = deployments::table
+        .select(DeploymentPo::as_select())
+        .find(id)
+        .first(conn)
+        .optional()?;
+    let Some(deployment) = deployment else { return Ok(None) };
+    with_hosts(deployment, conn).map(Some)
+}
+
+/// The most recent deployment of the app, whatever its outcome
+pub async fn latest(app_id: AppId, conn: &mut SqliteConn) -> Result<Option<Deployment>> {
+    let deployment = deployments::table
+        .select(DeploymentPo::as_select())
+        .filter(deployments::app_id.eq(app_id))
+        .order(deployments::started_at.desc())
+        .first(conn)
+        .optional()?;
+    let Some(deployment) = deployment else { return Ok(None) };
+    with_hosts(deployment, conn).map(Some)
+}
+
+/// The most recent successful deployment of the app with a version other than `hash`
+pub async fn last_succeeded_except(app_id: AppId, hash: &str, conn: &mut SqliteConn) -> Result<Option<Deployment>> {
+    let deployment = deployments::table
+        .select(DeploymentPo::as_select())
+        .filter(deployments::app_id.eq(app_id))
+        .filter(deployments::state.eq(DeploymentState::Succeeded))
+        .filter(deployments::hash.ne(hash))
+        .order(deployments::started_at.desc())
+        .first(conn)
+        .optional()?;
+    let Some(deployment) = deployment else { return Ok(None) };
+    with_hosts(deployment, conn).map(Some)
+}
+
+/// Those of `hosts` whose last finished deploy of the app was a failed one of version `hash`
+pub async fn failed_hosts(app_id: AppId, hash: &str, hosts: &[HostId], conn: &mut SqliteConn) -> Result<Vec<HostId>> {
+    let deployments: Vec<(DeploymentId, String)> = deployments::table
+        .select((deployments::id, deployments::hash))
+        .filter(deployments::app_id.eq(app_id))
+        .order(deployments::started_at.desc())
+        .load(conn)?;
+    let results: Vec<(DeploymentId, HostId, HostDeployState)> = deployment_hosts::table
+        .select((deployment_hosts::deployment_id, deployment_hosts::host_id, deployment_hosts::state))
+        .filter(deployment_hosts::deployment_id.eq_any(deployments.iter().map(|(id, _)| *id)))
+        .filter(deployment_hosts::host_id.eq_any(hosts))
+        .filter(deployment_hosts::state.eq_any([HostDeployState::Succeeded, HostDeployState::Failed]))
+        .load(conn)?;
+    Ok(newest_failed(hash, &deployments, results))
+}
+
+/// Hosts whose newest result, with `deployments` newest first, is a failed deploy of `hash`
+fn newest_failed(hash: &str, deployments: &[(DeploymentId, String)], results: Vec<(DeploymentId, HostId, HostDeployState)>) -> Vec<HostId> {
+    let mut by_deployment: HashMap<_, Vec<_>> = HashMap::new();
+    for (deployment_id, host_id, state) in results {
+        by_deployment.entry(deployment_id).or_default().push((host_id, state));
+    }
+    let mut seen = Vec::new();
+    let mut failed = Vec::new();
+    // newest first, only the first result of each host counts
+    for (id, deployment_hash) in deployments {
+        for &(host_id, state) in by_deployment.get(id).into_iter().flatten() {
+            if seen.contains(&host_id) {
+                continue;
+            }
+            seen.push(host_id);
+            if state == HostDeployState::Failed && deployment_hash == hash {
+                failed.push(host_id);
+            }
+        }
+    }
+    failed
+}
+
+pub async fn list(app_id: AppId, page: Pagination, conn: &mut SqliteConn) -> Result<PageList<Deployment>> {
+    let deployments: Vec<(DeploymentPo, i64)> = deployments::table
+        .select(DeploymentPo::as_select())
+        .filter(deployments::app_id.eq(app_id))
+        .order(deployments::started_at.desc())
+        .paginate(page.offset(), page.limit())
+        .load(conn)?;
+
+    let ids = deployments.iter().map(|(d, _)| d.id).collect::<Vec<_>>();
+    let deployments = PageList::from(deployments);
+    let hosts: Vec<DeploymentHostPo> = deployment_hosts::table
+        .select(DeploymentHostPo::as_select())
+        .filter(deployment_hosts::deployment_id.eq_any(ids))
+        .order(deployment_hosts::host_id)
+        .load(conn)?;
+
+    let mut groups = HashMap::new();
+    for host in hosts {
+        groups.entry(host.deployment_id).or_insert_with(Vec::new).push(host);
+    }
+    let data = deployments
+        .data
+        .into_iter()
+        .map(|d| {
+            let hosts = groups.remove(&d.id).unwrap_or_default();
+            Deployment::try_from((d, hosts))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(PageList {
+        total: deployments.total,
+        data,
+    })
+}
+
+fn with_hosts(deployment: DeploymentPo<'static>, conn: &mut SqliteConn) -> Result<Deployment> {
+    let hosts: Vec<DeploymentHostPo> = deployment_hosts::table
+        .select(DeploymentHostPo::as_select())
+        .filter(deployment_hosts::deployment_id.eq(deployment.id))
+        .order(deployment_hosts::host_id)
+        .load(conn)?;
+    Deployment::try_from((deployment, hosts))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn t_newest_failed() {
+        let (old, new) = (DeploymentId::from(1), DeploymentId::from(2));
+        let host = HostId::from;
+        let deployments = [(new, "v2".to_string()), (old, "v2".to_string())];
+        let results = vec![
+            // failed before, succeeded since
+            (old, host(1), HostDeployState::Failed),
+            (new, host(1), HostDeployState::Succeeded),
+            // succeeded before, failed since
+            (old, host(2), HostDeployState::Succeeded),
+            (new, host(2), HostDeployState::Failed),
+            (old, host(3), HostDeployState::Failed),
+        ];
+        assert_eq!(newest_failed("v2", &deployments, results.clone()), vec![host(2), host(3)]);
+        // a failed deploy of another version says nothing about this one
+        assert!(newest_failed("v3", &deployments, results).is_empty());
+    }
+}
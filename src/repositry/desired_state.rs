@@ -0,0 +1,49 @@
+use std::borrow::Cow;
+
+use anyhow::Result;
+use diesel::{prelude::*, upsert::excluded};
+
+use crate::{
+    application::{reconcile::DesiredState, AppId},
+    host::group::HostGroupId,
+    schema::desired_states,
+};
+
+use super::SqliteConn;
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Insertable, AsChangeset)]
+#[diesel(table_name = desired_states)]
+#[diesel(primary_key(app_id))]
+pub struct DesiredStatePo<'a> {
+    pub app_id: AppId,
+    pub hash: Cow<'a, str>,
+    pub group_id: HostGroupId,
+}
+
+pub async fn upsert(desired: &DesiredState, conn: &mut SqliteConn) -> Result<()> {
+    let desired = DesiredStatePo::from(desired);
+    diesel::insert_into(desired_states::table)
+        .values(&desired)
+        .on_conflict(desired_states::app_id)
+        .do_update()
+        .set((
+            desired_states::hash.eq(excluded(desired_states::hash)),
+            desired_states::group_id.eq(excluded(desired_states::group_id)),
+            desired_states::updated_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+pub async fn delete(app_id: AppId, conn: &mut SqliteConn) -> Result<usize> {
+    let count = diesel::delete(desired_states::table.find(app_id)).execute(conn)?;
+    Ok(count)
+}
+
+pub async fn list(conn: &mut SqliteConn) -> Result<Vec<DesiredState>> {
+    let desired: Vec<DesiredStatePo> = desired_states::table
+        .select(DesiredStatePo::as_select())
+        .order(desired_states::app_id)
+        .load(conn)?;
+    desired.into_iter().map(DesiredState::try_from).collect()
+}
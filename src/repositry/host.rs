@@ -1,10 +1,11 @@
 use anyhow::Result;
+use chrono::NaiveDateTime;
 use std::{borrow::Cow, net::Ipv4Addr};
 
 use crate::{
-    host::{Host, HostId},
+    host::{Host, HostId, HostState},
     http::Pagination,
-    schema::hosts,
+    schema::{app_instances, host_events, host_group_members, hosts},
 };
 use diesel::prelude::*;
 
@@ -16,6 +17,19 @@ pub struct HostPo<'a> {
     pub id: HostId,
     pub name: Cow<'a, str>,
     pub ip: Cow<'a, str>,
+    pub ssh_port: i32,
+    pub ssh_user: Cow<'a, str>,
+    /// file name in the ssh key dir, `None` for the global key
+    pub ssh_key: Option<Cow<'a, str>>,
+    pub envoy_version: Option<Cow<'a, str>>,
+    pub envoy_commit: Option<Cow<'a, str>>,
+    pub envoy_protocol: Option<i32>,
+    pub state: HostState,
+    pub last_seen_at: Option<NaiveDateTime>,
+    /// failed health checks in a row
+    pub failures: i32,
+    /// json of [`crate::host::facts::HostFacts`]
+    pub facts: Option<Cow<'a, str>>,
 }
 
 pub async fn save(host: &Host, conn: &mut SqliteConn) -> Result<()> {
@@ -30,6 +44,23 @@ pub async fn update(host: &Host, conn: &mut SqliteConn) -> Result<()> {
     Ok(())
 }
 
+/// Store what the last health check found, leaving the rest of the host alone
+pub async fn update_health(host: &Host, conn: &mut SqliteConn) -> Result<()> {
+    let po = HostPo::from(host);
+    diesel::update(hosts::table.find(host.id))
+        .set((
+            hosts::state.eq(po.state),
+            hosts::last_seen_at.eq(po.last_seen_at),
+            hosts::failures.eq(po.failures),
+            hosts::envoy_version.eq(po.envoy_version),
+            hosts::envoy_commit.eq(po.envoy_commit),
+            hosts::envoy_protocol.eq(po.envoy_protocol),
+            hosts::facts.eq(po.facts),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
 #[derive(derive_more::From)]
 pub enum HostIdent {
     Id(HostId),
@@ -43,15 +74,16 @@ where
     let id = HostIdent::from(id);
     match id {
         HostIdent::Id(id) => {
-            let host: HostPo = hosts::table.select(HostPo::as_select()).find(id).first(conn)?;
-            Ok(Some(Host::try_from(host)?))
+            let host: Option<HostPo> = hosts::table.select(HostPo::as_select()).find(id).first(conn).optional()?;
+            host.map(Host::try_from).transpose()
         }
         HostIdent::Ip(ip) => {
-            let host: HostPo = hosts::table
+            let host: Option<HostPo> = hosts::table
                 .select(HostPo::as_select())
                 .filter(hosts::ip.eq(ip.to_string()))
-                .first(conn)?;
-            Ok(Some(Host::try_from(host)?))
+                .first(conn)
+                .optional()?;
+            host.map(Host::try_from).transpose()
         }
     }
 }
@@ -64,3 +96,20 @@ pub async fn list(page: Pagination, conn: &mut SqliteConn) -> Result<PageList<Ho
     let host_list = PageList::from(hosts);
     Ok(host_list.try_convert()?)
 }
+
+pub async fn all(conn: &mut SqliteConn) -> Result<Vec<Host>> {
+    let hosts: Vec<HostPo> = hosts::table.select(HostPo::as_select()).order(hosts::name).load(conn)?;
+    hosts.into_iter().map(Host::try_from).collect()
+}
+
+/// Forget the host with its group memberships, app instances and events, deployment history is kept
+pub async fn delete(id: HostId, conn: &mut SqliteConn) -> Result<()> {
+    conn.transaction(|conn| {
+        diesel::delete(host_group_members::table.filter(host_group_members::host_id.eq(id))).execute(conn)?;
+        diesel::delete(app_instances::table.filter(app_instances::host_id.eq(id))).execute(conn)?;
+        diesel::delete(host_events::table.filter(host_events::host_id.eq(id))).execute(conn)?;
+        diesel::delete(hosts::table.find(id)).execute(conn)?;
+        diesel::QueryResult::Ok(())
+    })?;
+    Ok(())
+}
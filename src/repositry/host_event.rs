@@ -0,0 +1,55 @@
+use std::borrow::Cow;
+
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use crate::{
+    host::{
+        event::{HostEvent, HostEventId},
+        HostId, HostState,
+    },
+    schema::host_events,
+};
+
+use super::SqliteConn;
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Insertable)]
+#[diesel(table_name = host_events)]
+pub struct HostEventPo<'a> {
+    pub id: HostEventId,
+    pub host_id: HostId,
+    pub from_state: Option<HostState>,
+    pub state: HostState,
+    pub reason: Cow<'a, str>,
+    pub at: NaiveDateTime,
+}
+
+pub async fn save(event: &HostEvent, conn: &mut SqliteConn) -> Result<()> {
+    let event = HostEventPo::from(event);
+    diesel::insert_into(host_events::table).values(event).execute(conn)?;
+    Ok(())
+}
+
+/// Events of the host since `since`, the oldest first
+pub async fn list_since(host_id: HostId, since: NaiveDateTime, conn: &mut SqliteConn) -> Result<Vec<HostEvent>> {
+    let events: Vec<HostEventPo> = host_events::table
+        .select(HostEventPo::as_select())
+        .filter(host_events::host_id.eq(host_id))
+        .filter(host_events::at.ge(since))
+        .order(host_events::at)
+        .load(conn)?;
+    Ok(events.into_iter().map(HostEvent::from).collect())
+}
+
+/// The last event of the host before `at`, it tells the state the host was in at `at`
+pub async fn last_before(host_id: HostId, at: NaiveDateTime, conn: &mut SqliteConn) -> Result<Option<HostEvent>> {
+    let event: Option<HostEventPo> = host_events::table
+        .select(HostEventPo::as_select())
+        .filter(host_events::host_id.eq(host_id))
+        .filter(host_events::at.lt(at))
+        .order(host_events::at.desc())
+        .first(conn)
+        .optional()?;
+    Ok(event.map(HostEvent::from))
+}
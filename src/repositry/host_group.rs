@@ -0,0 +1,144 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use anyhow::Result;
+use diesel::prelude::*;
+
+use crate::{
+    host::{
+        group::{HostGroup, HostGroupId},
+        HostId,
+    },
+    http::Pagination,
+    schema::{desired_states, host_group_members, host_groups},
+};
+
+use super::{PageList, Paginate, SqliteConn};
+
+#[derive(Queryable, Selectable, Identifiable, Debug, Insertable, AsChangeset)]
+#[diesel(table_name = host_groups)]
+pub struct HostGroupPo<'a> {
+    pub id: HostGroupId,
+    pub name: Cow<'a, str>,
+}
+
+#[derive(Queryable, Selectable, Debug, Insertable)]
+#[diesel(table_name = host_group_members)]
+pub struct HostGroupMemberPo {
+    pub group_id: HostGroupId,
+    pub host_id: HostId,
+}
+
+pub async fn save(group: &HostGroup, conn: &mut SqliteConn) -> Result<()> {
+    let po = HostGroupPo::from(group);
+    conn.transaction(|conn| {
+        diesel::insert_into(host_groups::table).values(po).execute(conn)?;
+        insert_members(group.id, &group.hosts, conn)
+    })?;
+    Ok(())
+}
+
+/// Replace the members of the group
+pub async fn set_members(id: HostGroupId, hosts: &[HostId], conn: &mut SqliteConn) -> Result<()> {
+    conn.transaction(|conn| {
+        diesel::delete(host_group_members::table.filter(host_group_members::group_id.eq(id))).execute(conn)?;
+        insert_members(id, hosts, conn)
+    })?;
+    Ok(())
+}
+
+fn insert_members(id: HostGroupId, hosts: &[HostId], conn: &mut SqliteConnection) -> QueryResult<()> {
+    let members = hosts
+        .iter()
+        .map(|&host_id| HostGroupMemberPo { group_id: id, host_id })
+        .collect::<Vec<_>>();
+    diesel::insert_or_ignore_into(host_group_members::table)
+        .values(members)
+        .execute(conn)?;
+    Ok(())
+}
+
+pub async fn find(id: HostGroupId, conn: &mut SqliteConn) -> Result<Option<HostGroup>> {
+    let group = host_groups::table
+        .select(HostGroupPo::as_select())
+        .find(id)
+        .first(conn)
+        .optional()?;
+    let Some(group) = group else { return Ok(None) };
+
+    let members: Vec<HostGroupMemberPo> = host_group_members::table
+        .select(HostGroupMemberPo::as_select())
+        .filter(host_group_members::group_id.eq(id))
+        .order(host_group_members::host_id)
+        .load(conn)?;
+    HostGroup::try_from((group, members)).map(Some)
+}
+
+pub async fn list(page: Pagination, conn: &mut SqliteConn) -> Result<PageList<HostGroup>> {
+    let groups: Vec<(HostGroupPo, i64)> = host_groups::table
+        .select(HostGroupPo::as_select())
+        .order(host_groups::name)
+        .paginate(page.offset(), page.limit())
+        .load(conn)?;
+
+    let ids = groups.iter().map(|(g, _)| g.id).collect::<Vec<_>>();
+    let groups = PageList::from(groups);
+    let members: Vec<HostGroupMemberPo> = host_group_members::table
+        .select(HostGroupMemberPo::as_select())
+        .filter(host_group_members::group_id.eq_any(ids))
+        .order(host_group_members::host_id)
+        .load(conn)?;
+
+    let mut by_group = HashMap::new();
+    for member in members {
+        by_group.entry(member.group_id).or_insert_with(Vec::new).push(member);
+    }
+    let data = groups
+        .data
+        .into_iter()
+        .map(|g| {
+            let members = by_group.remove(&g.id).unwrap_or_default();
+            HostGroup::try_from((g, members))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(PageList { total: groups.total, data })
+}
+
+pub async fn all(conn: &mut SqliteConn) -> Result<Vec<HostGroup>> {
+    let groups: Vec<HostGroupPo> = host_groups::table
+        .select(HostGroupPo::as_select())
+        .order(host_groups::name)
+        .load(conn)?;
+    let mut members: Vec<HostGroupMemberPo> = host_group_members::table
+        .select(HostGroupMemberPo::as_select())
+        .order(host_group_members::host_id.desc())
+        .load(conn)?;
+
+    let mut by_group = HashMap::new();
+    while let Some(member) = members.pop() {
+        by_group.entry(member.group_id).or_insert_with(Vec::new).push(member);
+    }
+    groups
+        .into_iter()
+        .map(|g| {
+            let members = by_group.remove(&g.id).unwrap_or_default();
+            HostGroup::try_from((g, members))
+        })
+        .collect()
+}
+
+/// Fails while a desired state still targets the group
+pub async fn delete(id: HostGroupId, conn: &mut SqliteConn) -> Result<()> {
+    let used: i64 = desired_states::table
+        .filter(desired_states::group_id.eq(id))
+        .count()
+        .get_result(conn)?;
+    anyhow::ensure!(used == 0, "host group is used by {} desired states", used);
+
+    conn.transaction(|conn| {
+        diesel::delete(host_group_members::table.filter(host_group_members::group_id.eq(id))).execute(conn)?;
+        diesel::delete(host_groups::table.find(id)).execute(conn)?;
+        diesel::QueryResult::Ok(())
+    })?;
+    Ok(())
+}
@@ -3,7 +3,7 @@ use std::{path::PathBuf, sync::OnceLock};
 use anyhow::Result;
 use diesel::{
     query_builder::{AstPass, Query, QueryFragment, QueryId},
-    r2d2::{ConnectionManager, Pool, PooledConnection},
+    r2d2::{ConnectionManager, CustomizeConnection, Pool, PooledConnection},
     sql_types::BigInt,
     sqlite::Sqlite,
     QueryResult, RunQueryDsl, SqliteConnection,
@@ -12,8 +12,14 @@ use serde::{Deserialize, Serialize};
 
 use crate::settings::get_settings;
 
+pub mod app_instance;
 pub mod application;
+pub mod build;
+pub mod deployment;
+pub mod desired_state;
 pub mod host;
+pub mod host_event;
+pub mod host_group;
 
 #[derive(Debug, Deserialize)]
 pub struct SledCfg {
@@ -46,10 +52,25 @@ pub fn init(cfg: &SqlitePoolConfig) -> Result<&'static Pool<ConnectionManager<Sq
         .min_idle(Some(cfg.min_conn))
         .max_size(cfg.max_conn)
         .test_on_check_out(true)
+        .connection_customizer(Box::new(BusyTimeout))
         .build(manager)?;
     Ok(POOL.get_or_init(|| pool))
 }
 
+/// Hosts are synced and checked concurrently, their writes wait for each other instead of
+/// failing with `database is locked`
+#[derive(Debug)]
+struct BusyTimeout;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for BusyTimeout {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        diesel::sql_query("PRAGMA busy_timeout = 5000")
+            .execute(conn)
+            .map_err(diesel::r2d2::Error::QueryError)?;
+        Ok(())
+    }
+}
+
 pub async fn db_conn() -> Result<SqliteConn> {
     tokio::task::spawn_blocking(db_conn_sync).await?
 }
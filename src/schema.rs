@@ -1,11 +1,26 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    app_instances (app_id, host_id) {
+        app_id -> BigInt,
+        host_id -> BigInt,
+        hash -> Text,
+        install_dir -> Nullable<Text>,
+        state -> SmallInt,
+        reported_hash -> Nullable<Text>,
+        reported_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     app_versions (hash) {
         hash -> Text,
         app_id -> BigInt,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        artifact -> Nullable<Text>,
     }
 }
 
@@ -19,6 +34,92 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    builds (id) {
+        id -> BigInt,
+        app_id -> BigInt,
+        hash -> Text,
+        commit_hash -> Nullable<Text>,
+        state -> SmallInt,
+        exit_code -> Nullable<Integer>,
+        started_at -> Nullable<Timestamp>,
+        ended_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    deployment_hosts (deployment_id, host_id) {
+        deployment_id -> BigInt,
+        host_id -> BigInt,
+        state -> SmallInt,
+        install_dir -> Nullable<Text>,
+        exit_code -> Nullable<Integer>,
+        stdout -> Text,
+        stderr -> Text,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    deployments (id) {
+        id -> BigInt,
+        app_id -> BigInt,
+        hash -> Text,
+        triggered_by -> Text,
+        rollback_of -> Nullable<BigInt>,
+        state -> SmallInt,
+        started_at -> Timestamp,
+        ended_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        strategy -> Text,
+    }
+}
+
+diesel::table! {
+    desired_states (app_id) {
+        app_id -> BigInt,
+        hash -> Text,
+        group_id -> BigInt,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    host_events (id) {
+        id -> BigInt,
+        host_id -> BigInt,
+        from_state -> Nullable<SmallInt>,
+        state -> SmallInt,
+        reason -> Text,
+        at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    host_group_members (group_id, host_id) {
+        group_id -> BigInt,
+        host_id -> BigInt,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    host_groups (id) {
+        id -> BigInt,
+        name -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     hosts (id) {
         id -> BigInt,
@@ -26,11 +127,29 @@ diesel::table! {
         ip -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        ssh_port -> Integer,
+        ssh_user -> Text,
+        ssh_key -> Nullable<Text>,
+        envoy_version -> Nullable<Text>,
+        envoy_commit -> Nullable<Text>,
+        envoy_protocol -> Nullable<Integer>,
+        state -> SmallInt,
+        last_seen_at -> Nullable<Timestamp>,
+        failures -> Integer,
+        facts -> Nullable<Text>,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
+    app_instances,
     app_versions,
     applications,
+    builds,
+    deployment_hosts,
+    deployments,
+    desired_states,
+    host_events,
+    host_group_members,
+    host_groups,
     hosts,
 );
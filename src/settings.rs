@@ -17,6 +17,10 @@ pub struct Settings {
     pub envoy: EnvoyCfg,
     pub http_server: HttpServerCfg,
     pub sqlite: SqlitePoolConfig,
+    #[serde(default)]
+    pub reconciler: ReconcilerCfg,
+    #[serde(default)]
+    pub monitor: MonitorCfg,
 }
 
 #[derive(Deserialize, Debug, derive_more::Deref, derive_more::AsRef)]
@@ -53,6 +57,15 @@ impl DataDir {
         ssh
     }
 
+    pub fn artifact_dir(&self) -> PathBuf {
+        self.0.join("artifacts")
+    }
+
+    /// files received over http on their way to a host
+    pub fn upload_dir(&self) -> PathBuf {
+        self.0.join("uploads")
+    }
+
     pub fn app_dir(&self, name: &str) -> AppDir {
         let mut dir = self.0.join("applications");
         dir.push(name);
@@ -72,6 +85,35 @@ pub struct HttpServerCfg {
     pub port: u16,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ReconcilerCfg {
+    /// seconds between two reconcile rounds, 0 disables the background reconciler
+    pub interval_secs: u64,
+}
+
+impl Default for ReconcilerCfg {
+    fn default() -> Self {
+        Self { interval_secs: 60 }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MonitorCfg {
+    /// seconds between two health checks of every host, 0 disables the background monitor
+    pub interval_secs: u64,
+    /// seconds a host may take to answer a health check
+    pub timeout_secs: u64,
+}
+
+impl Default for MonitorCfg {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            timeout_secs: 5,
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! join_path {
     ($pre:expr, $child:expr) => {{
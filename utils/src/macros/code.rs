@@ -5,7 +5,7 @@
 /// 2. doc_csv()
 ///
 /// # Examples
-/// ```
+/// ```ignore
 ///
 /// code! {
 ///     mod = "user";  // 模块名
@@ -141,40 +141,25 @@ macro_rules! code {
     };
 }
 
-/// 执行一个返回值为 Result 的表达式，如果结果为 Err，打印一条错误日志
-/// 用于只记录而不处理错误的情况
-#[macro_export]
-macro_rules! log_if_err {
-    ($run:expr) => {
-        $crate::log_if_err!($run, stringify!($run))
-    };
-
-    ($run:expr, $msg:expr $(,)?) => {
-        if let Err(err) = $run {
-            ::tracing::error!(?err, concat!("FAILED: ", $msg))
-        }
-    };
-}
-
 /// 执行任意数量的表达式，任何一条的执行出错都会打印更丰富的信息，并直接抛出错误
 #[macro_export]
 macro_rules! log_err_ctx {
     ({$($runs:expr)*}) => {{
         let context = || {};
-        crate::log_err_ctx!(@invoke $($runs)*, context)
+        $crate::log_err_ctx!(@invoke $($runs)*, context)
     }};
 
     ({$($runs:expr)*} $(,$fields:ident)+ $(,)?) => {{
         let context = || {
             ::tracing::info!($(?$fields,)+);
         };
-        crate::log_err_ctx!(@invoke $($runs)*, context)
+        $crate::log_err_ctx!(@invoke $($runs)*, context)
     }};
 
     (@invoke $($runs:expr)*, $context:ident) => {{
         #[allow(redundant_semicolons)]
         {
-            $(;crate::log_err_ctx!(@closure $runs, $context))*
+            $(;$crate::log_err_ctx!(@closure $runs, $context))*
         }
     }};
 
@@ -427,14 +412,14 @@ pub trait Code: std::fmt::Display + std::fmt::Debug + Send + Sync + 'static {
 /// 因为孤儿规则，如果直接在当前 crate 中声明，使用这个 trait 的其他 crate 就无法为一些常用 Error 实现这个 trait,
 ///
 /// # Examples
-/// ```
+/// ```ignore
 /// define_code_trait!();
 ///
 /// code! {
 ///     mod = "user";
 ///     index = 10;
 ///     err_trait = Code;
-
+///
 ///     pub Password = 20 {
 ///         too_long = "密码太长了",
 ///         too_short = "密码太短了"
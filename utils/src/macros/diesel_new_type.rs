@@ -19,17 +19,17 @@ macro_rules! diesel_new_type {
                 deserialize::{self, FromSql},
                 serialize::{self, Output, ToSql},
             };
-            type Pg = diesel::sqlite::Sqlite;
+            type Sqlite = diesel::sqlite::Sqlite;
 
-            impl ToSql<$pg_type, Pg> for $type {
-                fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
-                    ToSql::<$pg_type, Pg>::to_sql(&self.0, out)
+            impl ToSql<$pg_type, Sqlite> for $type {
+                fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+                    ToSql::<$pg_type, Sqlite>::to_sql(&self.0, out)
                 }
             }
 
-            impl FromSql<$pg_type, Pg> for $type {
-                fn from_sql(bytes: <Pg as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
-                    let res = FromSql::<$pg_type, Pg>::from_sql(bytes)?;
+            impl FromSql<$pg_type, Sqlite> for $type {
+                fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+                    let res = FromSql::<$pg_type, Sqlite>::from_sql(bytes)?;
                     Ok(Self(res))
                 }
             }
@@ -44,18 +44,20 @@ macro_rules! diesel_new_type {
                 serialize::{self, Output, ToSql},
             };
 
-            type Pg = diesel::pg::Pg;
+            type Sqlite = diesel::sqlite::Sqlite;
 
-            impl ToSql<$pg_type, Pg> for $type {
-                fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+            impl ToSql<$pg_type, Sqlite> for $type {
+                fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+                    // sqlite binds by value, so the temporary can be handed over directly
                     let map_to = <$map_ty>::try_from(self)?;
-                    ToSql::<$pg_type, Pg>::to_sql(&map_to, &mut out.reborrow())
+                    out.set_value($crate::macros::diesel_new_type::SqliteBind::into_bind_value(map_to));
+                    Ok(serialize::IsNull::No)
                 }
             }
 
-            impl FromSql<$pg_type, Pg> for $type {
-                fn from_sql(bytes: <Pg as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
-                    let res: $map_ty = FromSql::<$pg_type, Pg>::from_sql(bytes)?;
+            impl FromSql<$pg_type, Sqlite> for $type {
+                fn from_sql(bytes: <Sqlite as Backend>::RawValue<'_>) -> deserialize::Result<Self> {
+                    let res: $map_ty = FromSql::<$pg_type, Sqlite>::from_sql(bytes)?;
                     Ok(Self::try_from(res)?)
                 }
             }
@@ -63,6 +65,28 @@ macro_rules! diesel_new_type {
     };
 }
 
+/// What a `try_from` type of [`diesel_new_type`] is bound as, the same way diesel binds it
+#[cfg(feature = "diesel")]
+pub trait SqliteBind {
+    fn into_bind_value<'a>(self) -> diesel::sqlite::SqliteBindValue<'a>;
+}
+
+#[cfg(feature = "diesel")]
+macro_rules! sqlite_bind {
+    ($($ty:ty => $bind:ty),* $(,)?) => {
+        $(
+            impl SqliteBind for $ty {
+                fn into_bind_value<'a>(self) -> diesel::sqlite::SqliteBindValue<'a> {
+                    <$bind>::from(self).into()
+                }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "diesel")]
+sqlite_bind!(i16 => i32, i32 => i32, i64 => i64, f64 => f64, String => String, Vec<u8> => Vec<u8>);
+
 #[cfg(feature = "diesel")]
 #[macro_export]
 macro_rules! diesel_enum {
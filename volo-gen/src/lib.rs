@@ -3,3 +3,7 @@ mod gen {
 }
 
 pub use gen::volo_gen::*;
+
+/// Bumped whenever `node.proto` changes incompatibly or gains an rpc the operator relies on,
+/// envoys reporting another one need an upgrade
+pub const PROTOCOL_VERSION: u32 = 7;